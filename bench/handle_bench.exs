@@ -0,0 +1,69 @@
+# Compares the NIF allocations and the time of `run/2` and of the reused `handle_run/2`
+# over repeated frames of the same shape.
+#
+#     YOLO_FAST_NMS_FEATURES=alloc_count mix compile --force
+#     YOLO_FAST_NMS_FEATURES=alloc_count mix run bench/handle_bench.exs
+#
+# Without the `alloc_count` feature the allocations are reported as `n/a`.
+# Prints one CSV line for each path.
+
+defmodule YoloFastNMS.HandleBench do
+  @classes 80
+  @candidates 8_400
+  @frames 30
+  @options [prob_threshold: 0.25, iou_threshold: 0.5]
+
+  def run do
+    :rand.seed(:exsss, {1, 2, 3})
+    frame = frame()
+    handle = YoloFastNMS.handle_new(Nx.shape(frame), @options)
+    # warmup, the first run of the handle allocates its buffers
+    YoloFastNMS.handle_run(handle, frame)
+
+    IO.puts("path,frames,allocations_per_frame,median_us")
+    report(:run, fn -> YoloFastNMS.run(frame, @options) end)
+    report(:handle_run, fn -> YoloFastNMS.handle_run(handle, frame) end)
+  end
+
+  defp report(path, fun) do
+    allocations_before = allocations()
+    times = for _ <- 1..@frames, do: fun |> :timer.tc() |> elem(0)
+    allocations_after = allocations()
+
+    allocations_per_frame =
+      if allocations_before == :na,
+        do: "n/a",
+        else: div(allocations_after - allocations_before, @frames)
+
+    median_us = times |> Enum.sort() |> Enum.at(div(@frames, 2))
+    IO.puts(Enum.join([path, @frames, allocations_per_frame, median_us], ","))
+  end
+
+  defp allocations do
+    YoloFastNMS.allocations_nif()
+  rescue
+    ErlangError -> :na
+  end
+
+  # {84, 8400} like the YOLOv8 output, boxes spread over a 640x640 image
+  defp frame do
+    rows =
+      for _ <- 1..@candidates do
+        class_idx = :rand.uniform(@classes) - 1
+        probs = List.replace_at(List.duplicate(0.0, @classes), class_idx, :rand.uniform())
+
+        [
+          640 * :rand.uniform(),
+          640 * :rand.uniform(),
+          20 + 40 * :rand.uniform(),
+          20 + 40 * :rand.uniform()
+        ] ++ probs
+      end
+
+    rows
+    |> Nx.tensor(type: {:f, 32})
+    |> Nx.transpose(axes: [1, 0])
+  end
+end
+
+YoloFastNMS.HandleBench.run()
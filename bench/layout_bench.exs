@@ -0,0 +1,62 @@
+# Compares the layouts of the kept boxes scanned by the hard NMS over 8400-detection frames:
+# the struct of arrays of the f32 IoU (`precision: :f32`) and of the fixed-point IoU
+# (`precision: :fixed`, without divisions), and the `BBox` structs of the f64 IoU
+# (`precision: :f64`, same loop with the f64 division).
+#
+#     mix run bench/layout_bench.exs
+#
+# The crowded frames have the most kept boxes per class, where the inner loop dominates.
+# Prints one CSV line for each layout/distribution.
+
+defmodule YoloFastNMS.LayoutBench do
+  @candidates 8_400
+  @iterations 20
+  @layouts [soa: :f32, soa_fixed: :fixed, aos: :f64]
+
+  def run do
+    IO.puts("layout,distribution,candidates,kept,iterations,median_us")
+
+    for distribution <- [:sparse, :crowd] do
+      :rand.seed(:exsss, {1, 2, 3})
+      tensor = distribution |> rows() |> Nx.tensor(type: {:f, 32})
+
+      for {layout, precision} <- @layouts do
+        options = [prob_threshold: 0.25, iou_threshold: 0.5, transpose: false, precision: precision]
+        kept = tensor |> YoloFastNMS.run(options) |> length()
+        median_us = median_us(fn -> YoloFastNMS.run(tensor, options) end)
+
+        IO.puts(Enum.join([layout, distribution, @candidates, kept, @iterations, median_us], ","))
+      end
+    end
+  end
+
+  defp median_us(fun) do
+    1..@iterations
+    |> Enum.map(fn _ -> fun |> :timer.tc() |> elem(0) end)
+    |> Enum.sort()
+    |> Enum.at(div(@iterations, 2))
+  end
+
+  # 80 classes over the whole 640x640 image, few kept boxes per class
+  defp rows(:sparse) do
+    for _ <- 1..@candidates do
+      row([uniform(640), uniform(640), 20 + uniform(40), 20 + uniform(40)], :rand.uniform(80) - 1)
+    end
+  end
+
+  # a single class of small boxes, thousands of kept boxes
+  defp rows(:crowd) do
+    for _ <- 1..@candidates do
+      row([uniform(640), uniform(640), 4 + uniform(8), 4 + uniform(8)], 0)
+    end
+  end
+
+  defp row(bbox, class_idx) do
+    probs = List.duplicate(0.0, 80)
+    bbox ++ List.replace_at(probs, class_idx, 0.3 + 0.7 * :rand.uniform())
+  end
+
+  defp uniform(max), do: max * :rand.uniform()
+end
+
+YoloFastNMS.LayoutBench.run()
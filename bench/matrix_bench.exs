@@ -0,0 +1,51 @@
+# Benchmarks Matrix NMS over dense frames of 5000 boxes per class.
+#
+#     mix run bench/matrix_bench.exs
+#     YOLO_FAST_NMS_FEATURES=parallel mix compile --force
+#     YOLO_FAST_NMS_FEATURES=parallel mix run bench/matrix_bench.exs
+#
+# Run it with and without the `parallel` feature to compare the scalar and the
+# multi-core decays, the kept boxes are the same (`cargo test --features parallel` in
+# native/yolofastnms checks the decays of a dense frame). Prints one CSV line for each size.
+
+defmodule YoloFastNMS.MatrixBench do
+  @classes 2
+  @iterations 5
+  @boxes_per_class [1_000, 5_000]
+
+  def run do
+    IO.puts("parallel,classes,boxes_per_class,kept,iterations,median_us")
+    parallel = "parallel" in String.split(System.get_env("YOLO_FAST_NMS_FEATURES", ""), ",")
+
+    for boxes_per_class <- @boxes_per_class do
+      :rand.seed(:exsss, {1, 2, 3})
+      tensor = boxes_per_class |> rows() |> Nx.tensor(type: {:f, 32})
+
+      options = [prob_threshold: 0.25, transpose: false, method: :matrix]
+      kept = tensor |> YoloFastNMS.run(options) |> length()
+      median_us = median_us(fn -> YoloFastNMS.run(tensor, options) end)
+
+      IO.puts(Enum.join([parallel, @classes, boxes_per_class, kept, @iterations, median_us], ","))
+    end
+  end
+
+  defp median_us(fun) do
+    1..@iterations
+    |> Enum.map(fn _ -> fun |> :timer.tc() |> elem(0) end)
+    |> Enum.sort()
+    |> Enum.at(div(@iterations, 2))
+  end
+
+  # dense crowd of each class packed in a region of the 640x640 image
+  defp rows(boxes_per_class) do
+    for class_idx <- 0..(@classes - 1), _ <- 1..boxes_per_class do
+      bbox = [200 + uniform(240), 200 + uniform(240), 30 + uniform(50), 30 + uniform(50)]
+      probs = List.duplicate(0.0, @classes)
+      bbox ++ List.replace_at(probs, class_idx, 0.3 + 0.7 * :rand.uniform())
+    end
+  end
+
+  defp uniform(max), do: max * :rand.uniform()
+end
+
+YoloFastNMS.MatrixBench.run()
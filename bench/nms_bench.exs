@@ -0,0 +1,73 @@
+# Benchmarks the NMS methods over synthetic detection sets of varying density.
+#
+#     mix run bench/nms_bench.exs
+#
+# Prints one CSV line for each method/distribution/size, so it can be tracked in CI.
+
+defmodule YoloFastNMS.Bench do
+  @classes 80
+  @iterations 20
+  @sizes [1_000, 4_000, 8_400]
+  @methods [:hard, :soft, :matrix]
+
+  def run do
+    IO.puts("method,distribution,candidates,kept,iterations,median_us,candidates_per_sec")
+
+    for distribution <- [:sparse, :crowd, :nested], size <- @sizes do
+      :rand.seed(:exsss, {1, 2, 3})
+      tensor = distribution |> rows(size) |> Nx.tensor(type: {:f, 32})
+
+      for method <- @methods do
+        options = [prob_threshold: 0.25, iou_threshold: 0.5, transpose: false, method: method]
+        kept = tensor |> YoloFastNMS.run(options) |> length()
+        median_us = median_us(fn -> YoloFastNMS.run(tensor, options) end)
+        per_sec = round(size / (median_us / 1_000_000))
+
+        IO.puts(Enum.join([method, distribution, size, kept, @iterations, median_us, per_sec], ","))
+      end
+    end
+  end
+
+  defp median_us(fun) do
+    1..@iterations
+    |> Enum.map(fn _ -> fun |> :timer.tc() |> elem(0) end)
+    |> Enum.sort()
+    |> Enum.at(div(@iterations, 2))
+  end
+
+  # boxes spread over the whole 640x640 image, few overlaps
+  defp rows(:sparse, size) do
+    for _ <- 1..size do
+      bbox = [uniform(640), uniform(640), 20 + uniform(40), 20 + uniform(40)]
+      row(bbox, :rand.uniform(@classes) - 1)
+    end
+  end
+
+  # dense crowd: many large boxes of a few classes packed in a small region
+  defp rows(:crowd, size) do
+    for _ <- 1..size do
+      bbox = [200 + uniform(200), 200 + uniform(200), 40 + uniform(40), 40 + uniform(40)]
+      row(bbox, :rand.uniform(5) - 1)
+    end
+  end
+
+  # concentric boxes of growing size around a few centers
+  defp rows(:nested, size) do
+    centers = for _ <- 1..20, do: {uniform(640), uniform(640)}
+
+    for i <- 1..size do
+      {cx, cy} = Enum.at(centers, rem(i, 20))
+      side = 10 + rem(i, 50) * 4
+      row([cx, cy, side, side], rem(i, 10))
+    end
+  end
+
+  defp row(bbox, class_idx) do
+    probs = List.duplicate(0.0, @classes)
+    bbox ++ List.replace_at(probs, class_idx, 0.3 + 0.7 * :rand.uniform())
+  end
+
+  defp uniform(max), do: max * :rand.uniform()
+end
+
+YoloFastNMS.Bench.run()
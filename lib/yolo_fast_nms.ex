@@ -13,12 +13,133 @@ defmodule YoloFastNMS do
 
   If your model outputs `{columns, rows}` (e.g., `{84, 8400}`), set `transpose: true` (default) in `run/2` options.
   """
-  use Rustler, otp_app: :yolo_fast_nms, crate: "yolofastnms"
+  # `YOLO_FAST_NMS_FEATURES` (comma separated) enables optional cargo features of the NIF,
+  # like `alloc_count` for the benchmarks, `parallel` for the multi-core Matrix NMS or `threads`
+  # for the hard NMS of the classes on scoped threads, without the rayon dependency.
+  # Changing it requires `mix compile --force`.
+  use Rustler,
+    otp_app: :yolo_fast_nms,
+    crate: "yolofastnms",
+    features: "YOLO_FAST_NMS_FEATURES" |> System.get_env("") |> String.split(",", trim: true)
 
   @default_options [
     prob_threshold: 0.25,
     iou_threshold: 0.5,
-    transpose: true
+    transpose: true,
+    dedup: false,
+    dedup_tolerance: 0.0,
+    agnostic: false,
+    class_priorities: nil,
+    format: :list,
+    class_probs: false,
+    min_results: 0,
+    obb: false,
+    group_by_class: false,
+    score_type: :sigmoid,
+    margin_threshold: 0.0,
+    multi_label: false,
+    method: :hard,
+    sigma: 0.5,
+    proximity_threshold: 0.5,
+    radius: 8.0,
+    letterbox: nil,
+    offset_x: 0.0,
+    offset_y: 0.0,
+    fingerprint: false,
+    argmaxed: false,
+    score_column: nil,
+    class_column: nil,
+    threshold_inclusive: false,
+    zero_union_iou: :zero,
+    iou_eps: 0.0,
+    top_n: nil,
+    auto_transpose: false,
+    class_tie_epsilon: nil,
+    threads: 1,
+    temporal_iou: nil,
+    coverage: false,
+    candidate_budget: nil,
+    corners: false,
+    group_column: false,
+    parse_mode: :lenient,
+    area_weights: nil,
+    min_size: 0.0,
+    class_min_sizes: nil,
+    duplicate_rows: false,
+    class_centroids: false,
+    sort: :nms,
+    sort_order: :desc,
+    calibration: nil,
+    grid_max: nil,
+    class_scores: nil,
+    palette_size: nil,
+    oversize_policy: :keep,
+    byte_offset: 0,
+    top_k_classes: 1,
+    min_support: 0,
+    support_iou: nil,
+    match_tolerance: nil,
+    valid_columns: nil,
+    min_center_distance: nil,
+    sorted_classes: true,
+    force_keep_threshold: nil,
+    band_low: nil,
+    band_high: nil,
+    endianness: :native,
+    objectness_classes: nil,
+    never_suppress: nil,
+    prior_grid: nil,
+    row_offset: 0,
+    row_count: nil,
+    row_index: false,
+    scale: 1.0,
+    pixel_inclusive: false,
+    input_sorted: false,
+    image_size: nil,
+    clamp: false,
+    clamped: false,
+    objectness: false,
+    score_combine: :product,
+    precision: :f32,
+    metadata: false,
+    tie_prefer: :first,
+    sanity_check: false,
+    display: false,
+    display_threshold: nil,
+    overlap_metric: :iou,
+    max_candidates_per_class: nil,
+    class_names: nil,
+    geometry: :kept,
+    class_sigmas: nil,
+    suppressed: false,
+    keep_classes: nil,
+    relabel: false
+  ]
+
+  # the suppression options that `handle_run/3` can override for a single run
+  @overridable_options [
+    :prob_threshold,
+    :iou_threshold,
+    :method,
+    :overlap_metric,
+    :sigma,
+    :agnostic,
+    :threshold_inclusive,
+    :iou_eps,
+    :precision
+  ]
+
+  # options adding keys to the detections, only available with `format: :map`
+  @map_only_options [
+    :class_probs,
+    :row_index,
+    :clamped,
+    :display,
+    :class_names,
+    :temporal_iou,
+    :corners,
+    :class_scores,
+    :palette_size
   ]
 
   @doc """
@@ -28,25 +149,148 @@ defmodule YoloFastNMS do
 
     - `tensor`: An `Nx.Tensor` with shape `{rows, columns}` or `{1, rows, columns}`. Each row is a detection candidate, with the first 4 columns as bounding box parameters (`cx`, `cy`, `w`, `h`) and the remaining columns as class probabilities.
     - `options`: Keyword list of options:
-      - `:prob_threshold` (float, default: 0.25) — Minimum probability threshold for detection confidence.
-      - `:iou_threshold` (float, default: 0.5) — IoU threshold for overlap suppression.
+      - `:prob_threshold` (float, default: 0.25) — Minimum probability threshold for detection confidence, a box passes when its `prob` is greater than or equal to it. Must be non-negative. Above 1.0 no box can pass and an empty result is returned without running the NMS, unless `:area_weights`, `:prior_grid` or `:calibration` rescale the probabilities, possibly above 1.0. The tensor is still parsed for `:sanity_check`, `:duplicate_rows`, `parse_mode: :strict` and the metadata.
+      - `:iou_threshold` (float, default: 0.5) — IoU threshold for overlap suppression. Must be between 0 and 1. A box is suppressed when its IoU with a more probable kept box is strictly greater than the threshold, a box overlapping exactly at the threshold is kept (see `:threshold_inclusive`). `0.0` keeps only the most probable box of each class (of all the boxes with `agnostic: true`), even if the others don't overlap it.
       - `:transpose` (boolean, default: true) — Whether to transpose the input tensor (set to true if your tensor shape is `{columns, rows}`).
+      - `:auto_transpose` (boolean, default: false) — Whether to infer `:transpose` from the tensor shape, assuming the smaller dimension is the features (like the 84 of a `{84, 8400}` output): `transpose: true` when there are fewer rows than columns, `false` when there are more. An explicit `:transpose` is always used as given, and a square tensor keeps the default. With `metadata: true` the metadata has the chosen `transpose`.
+      - `:parse_mode` (`:lenient` or `:strict`, default: `:lenient`) — What to do with the malformed rows: NaN, infinite or out of range (above 2^24 in absolute value) coordinates, a negative width or height, or an invalid `:argmaxed` class or `:group_column` id. `:lenient` skips them, for the resilience in production, and counts them in the `:dropped_rows` of the metadata. `:strict` raises an `ErlangError` with their count, to fail fast in an offline validation. A binary of the wrong size always raises. The stream API always skips them.
+      - `:sanity_check` (boolean, default: false) — Whether to inspect a sample of 64 detections and raise an `ErlangError` when most of the boxes have a non-positive size or are larger than their center coordinates (`w > cx` and `h > cy`, always true for `xyxy` boxes). It's the sign of boxes in another encoding or of a wrong `:transpose`, that would otherwise return nonsense boxes.
+      - `:valid_columns` (integer, default: nil) — Number of features of each detection to parse (the columns, or the rows with `transpose: true`), the ones after are padding and ignored. For the engines padding the class dimension to a hardware-friendly multiple, like a TensorRT output of 96 features for the 84 of YOLOv8, fed directly without slicing. The `:obb` angle, the `:argmaxed` class and the `:group_column` are the last of the valid features. `nil` for all the features.
+      - `:row_offset` (integer, default: 0) — Index of the first detection to consider, to run the NMS over a window of a large tensor without slicing it.
+      - `:row_count` (integer, default: nil) — Number of detections to consider from `:row_offset`, `nil` for all the remaining ones. The window must be within the tensor detections (the rows, or the columns with `transpose: true`).
+      - `:threshold_inclusive` (boolean, default: false) — Whether a box overlapping exactly at `:iou_threshold` is suppressed too (`iou >= iou_threshold`, like some other NMS implementations) instead of kept.
+      - `:zero_union_iou` (`:zero` or `:one`, default: `:zero`) — The IoU of two zero-area boxes, whose union is 0. With `:one` two identical zero-area boxes (same center, zero size, like the point detections or the tiny boxes collapsed by the clamping) have an IoU of 1.0 instead of 0.0, so the duplicates are suppressed. The zero-area boxes at different places still have an IoU of 0.0.
+      - `:iou_eps` (number, default: 0.0) — Epsilon added to the denominator of the IoU, `intersection / (union + iou_eps)`, bounding the IoU of the tiny boxes below 1 and damping their unstable ratios. `0.0` keeps the exact IoU.
+      - `:tie_prefer` (`:first`, `:larger_area` or `:smaller_area`, default: `:first`) — Which box is kept when overlapping boxes have the same probability: the first one in the tensor, or the one with the larger (more context) or the smaller (tighter) area.
+      - `:min_support` (integer, default: 0) — Noise filter of the isolated detections: a kept box is dropped when fewer than `:min_support` other candidates of its class (of any class with `agnostic: true`) overlap it by at least `:support_iou` before the suppression, since the genuine objects usually produce a cluster of candidates. Unlike `:prob_threshold`, it drops a confident box with no support. The `:never_suppress` boxes are always kept. Not supported with `suppressed: true`. `0` for no filter.
+      - `:support_iou` (number, default: nil) — The overlap of a candidate supporting a kept box for `:min_support`, `nil` for the `:iou_threshold`.
+      - `:max_candidates_per_class` (integer, default: nil) — Maximum number of candidates of each class going through the NMS, the most probable ones. It's a latency safeguard for real-time, not an accuracy feature: it bounds the sort and the quadratic suppression of a pathological frame with thousands of candidates of the same class, but the boxes over the cap are dropped even if they'd survive the NMS. `nil` for no limit.
+      - `:force_keep_threshold` (number, default: nil) — The candidates with a `prob` at or above it are always kept, even when the NMS suppresses them, since at that confidence two overlapping boxes are likely two real objects (like a dense crowd). They still suppress the less probable boxes, and are appended to the NMS survivors without the exact repeats of a kept box. Not supported with `suppressed: true`. `nil` for the plain NMS.
+      - `:band_low` (number, default: nil) — Confidence band of the NMS, with `:band_high`: the candidates with a `prob` below `band_low` are dropped. `nil` for no lower band.
+      - `:band_high` (number, default: nil) — The candidates with a `prob` at or above it pass through untouched: they are neither suppressed nor suppress the others, and are appended to the NMS survivors of the band. `nil` for no upper band.
+      - `:sorted_classes` (boolean, default: true) — Whether the classes are suppressed, and returned, in ascending `class_idx` order, so the detections are in the same order across the runs and the Rust versions, like for the golden-file tests. With `false` the order of the classes is the one of a hash set, slightly cheaper but not deterministic. The detections of each class are sorted by `prob` descending either way.
+      - `:min_center_distance` (number, default: nil) — Final spacing constraint after the NMS, across all the classes: when the centers of two kept boxes are closer than `:min_center_distance` (in the model coordinates), only the more probable one is kept, like for a placement application. Unlike `method: :point`, it runs after the suppression of each class. Applied before `:top_n`, not supported with `suppressed: true`. `nil` for no constraint.
+      - `:top_n` (integer, default: nil) — Returns only the `top_n` most probable detections after the NMS, sorted by `prob` descending across all the classes, like "the 30 most confident detections". `0` sorts all of them without truncating. `nil` keeps the NMS order.
+      - `:sort` (`:nms` or `:raster`, default: `:nms`) — Order of the returned detections. `:nms` is the order of the NMS (by class, then by `prob`). `:raster` sorts them top-to-bottom then left-to-right, by `cy` then `cx` in the model coordinates (the ties by `prob` descending, then class), an order that depends only on the kept boxes, for the overlays and the golden tests. Applied after `:top_n`.
+      - `:sort_order` (`:desc` or `:asc`, default: `:desc`) — Order of the candidates in the greedy loop of `method: :hard`. `:desc` is the standard NMS, the most probable box of each overlapping cluster is kept. `:asc` is experimental, for ablations: the least probable boxes come first and suppress the more probable ones, generally keeping worse representatives. Requires `method: :hard`.
+      - `:grid_max` (number, default: nil) — Approximate pre-filter of the dense outputs: the candidates passing `:prob_threshold` are partitioned in square cells of `:grid_max` pixels (in the model coordinates) by their center, and only the most probable candidate of each class in each cell goes through the NMS. It cuts the candidates of a crowded frame, but drops the boxes of the same class with their centers in the same cell, like two overlapping people. `nil` for no pre-filter.
+      - `:candidate_budget` (integer, default: nil) — Global latency budget: when more than `:candidate_budget` candidates pass `:prob_threshold`, only the most probable `:candidate_budget` of them, whatever their class, go through the NMS. They are found with a partial selection, without sorting all the candidates. With `metadata: true` the metadata has `truncated: true` when candidates were dropped. Unlike `:max_candidates_per_class`, it's a single knob bounding the work of the whole frame. `nil` for no budget.
+      - `:input_sorted` (boolean, default: false) — Hint that the detections are already sorted by `prob` descending (like the output of a model exported with a top-k), so the NMS skips sorting them. Ignored with `multi_label: true`, exact `dedup: true`, `:class_priorities` and `:tie_prefer`, which change the order. The order is checked only in the debug builds of the NIF, unsorted input with the hint gives wrong results.
+      - `:dedup` (boolean, default: false) — Whether to collapse duplicated boxes before NMS, keeping the most probable one. Two boxes are duplicates when they have the same class and their `cx`, `cy`, `w`, `h` match within `:dedup_tolerance`.
+      - `:dedup_tolerance` (float, default: 0.0) — Maximum coordinate difference between duplicated boxes. The default only collapses exact duplicates.
+      - `:match_tolerance` (number, default: nil) — Single coordinate tolerance of the "same box", so a box that moved sub-pixel is the same box everywhere: two boxes are the same when their `cx`, `cy`, `w`, `h` differ at most by `:match_tolerance` in the model coordinates. It replaces `:dedup_tolerance` for `dedup: true`, matches a previous box in the `:temporal_iou` tracking even below the IoU, and rounds the coordinates of the `:fingerprint` to its multiples. `nil` for the exact boxes (and the `:dedup_tolerance`).
+      - `:agnostic` (boolean, default: false) — Whether to suppress overlapping boxes regardless of their class.
+      - `:never_suppress` (list of integers, default: nil) — Classes whose detections bypass the suppression (like a full-frame "scene type" pseudo-detection): they are neither suppressed nor suppress the others, and are returned after the other detections. They are still filtered by `:prob_threshold`.
+      - `:keep_classes` (list of integers, default: nil) — Only the detections of these classes are kept, the rows whose most probable class isn't in the list are skipped (with `multi_label: true`, each class is checked).
+      - `:relabel` (boolean, default: false) — Whether to number the `:keep_classes` contiguously, returning as `class_idx` the index of the class in `:keep_classes` (for a color table or a head indexed `0..k`). With `metadata: true` the metadata has the `class_mapping` `%{class_idx => original_class_idx}` to invert it. Requires `:keep_classes`.
+      - `:class_priorities` (map, default: nil) — Map of `class_idx => weight` used in agnostic mode to order the boxes by `prob * weight` (classes not in the map have weight 1.0), so a higher-priority class can suppress a more probable box of another class. The weights must be positive. The returned `prob` is not weighted.
+      - `:geometry` (`:kept` or `:union`, default: `:kept`) — The geometry of the boxes kept by `method: :hard`: their own, or expanded to enclose the boxes they suppressed, to better cover the large objects detected in parts. The probability is the one of the kept box. Not supported with `:obb`.
+      - `:overlap_metric` (`:iou`, `:iomin` or `:iomax`, default: `:iou`) — The overlap measure compared with `:iou_threshold`, used by all the methods: the intersection over the union, over the smaller area (IoMin, 1 for a box nested in a larger one, to remove the nested duplicates) or over the larger area (IoMax).
+      - `:pixel_inclusive` (boolean, default: false) — Whether the IoU treats the box edges as pixels, counting `x2 - x1 + 1` pixels in the intersection and in the areas, instead of the continuous `x2 - x1`. It changes the IoU of small boxes, use it when the labels follow the pixel counting convention. Not used by the rotated IoU of `obb: true`.
+      - `:precision` (`:f32`, `:f64` or `:fixed`, default: `:f32`) — Arithmetic of the IoU. With large images the f32 areas (above 2^24 pixels) lose precision, `:f64` divides them in f64 and the IoU is rounded only once. `:fixed` is an approximate fast mode for the embedded targets without a fast float division: the IoU is computed in integer arithmetic, in steps of 1/1024. It's truncated by less than 0.001, so only the boxes overlapping within 0.001 of `:iou_threshold` can be kept or suppressed differently, and `:iou_eps` is not applied. Not used by the rotated IoU of `obb: true`.
+      - `:method` (`:hard`, `:soft`, `:matrix`, `:confluence` or `:point`, default: `:hard`) — The suppression algorithm. `:hard` drops the boxes overlapping a more probable one above `:iou_threshold`. `:soft` (Soft-NMS) and `:matrix` (Matrix NMS) instead decay the `prob` of the overlapping boxes with a gaussian of their IoU, dropping them once below `:prob_threshold`; they don't use `:iou_threshold`. `:confluence` clusters the boxes by the proximity of their corners instead of the IoU, keeping the box of each cluster closest to the others (weighted by its `prob`); it handles the same object boxes with a low overlap better. `:point` compares only the centers of the boxes, dropping the boxes with the center within `:radius` of the center of a more probable box, for the center-based models (CenterNet) without reliable box sizes.
+      - `:threads` (integer, default: 1) — Number of OS threads running the hard NMS of the different classes in parallel, with the same results. Only used by a NIF compiled with the `threads` feature (`YOLO_FAST_NMS_FEATURES=threads`), which doesn't depend on rayon; ignored otherwise.
+      - `:sigma` (float, default: 0.5) — Gaussian decay parameter of `:soft` and `:matrix`, the decay is `exp(-iou² / sigma)`. Lower values suppress more. Must be positive.
+      - `:class_sigmas` (map, default: nil) — Map of `class_idx => sigma` overriding `:sigma` for the boxes of some classes, like a gentler decay for the crowded classes. The classes not in the map use `:sigma`. The sigmas must be positive.
+      - `:radius` (number, default: 8.0) — Distance in pixels between two centers below which (inclusive) `method: :point` drops the less probable box.
+      - `:proximity_threshold` (float, default: 0.5) — Proximity below which two boxes are in the same `:confluence` cluster. The proximity is the Manhattan distance of the box corners, normalized by the extent of the two boxes (0 for the same box, up to 4).
+      - `:min_results` (integer, default: 0) — Minimum number of detections to return. When fewer boxes survive the NMS, the result is backfilled with the most probable boxes filtered out by `:prob_threshold` (skipping the ones overlapping a kept box). The backfilled boxes may be below the threshold. Not supported by the stream API, which doesn't buffer the filtered out boxes.
+      - `:obb` (boolean, default: false) — Whether the model outputs oriented bounding boxes (like YOLOv8-OBB), with the rotation angle in radians as the last column, after the class probabilities. The overlap is then computed with the rotated IoU and the angle is appended to each detection (`[cx, cy, w, h, prob, class_idx, angle]`, or the `:angle` key with `format: :map`).
+      - `:group_column` (boolean, default: false) — Whether the last column of each row is a group id, as a float, like the tile of the detection in the tensor of a tiled detector. The boxes are then only suppressed by the boxes of the same group and class (of the same group with `agnostic: true`), the adjacent tiles are suppressed separately. The id comes after the angle with `obb: true`, it's rounded to the nearest integer and the rows with a NaN, negative or too large id are skipped. The map detections have the `:group` key.
+      - `:argmaxed` (boolean, default: false) — Whether the rows are already argmaxed by the exporter, `[cx, cy, w, h, score, class_idx]` with the class id as a float, instead of the class probabilities. The class id is rounded to the nearest integer, the rows with a NaN, negative or too large id are skipped. The score and the class id come after the objectness with `objectness: true`, before the angle with `obb: true`. Not supported with `multi_label: true`.
+      - `:score_column`, `:class_column` (integer, default: nil) — The most general form of `:argmaxed`: the final confidence and the class id (as a float, rounded like `:argmaxed`) are read from these two feature indexes, anywhere after the box (4 and above) and before the angle with `obb: true`, the other features are ignored. The `prob` is the score as it is, without the objectness. Both or none, not supported with `argmaxed: true`, `objectness: true` and `multi_label: true`. A column out of the features raises an `ErlangError`.
+      - `:objectness` (boolean, default: false) — Whether the model outputs an objectness score as the 5th column, before the class probabilities (like YOLOv5). The `prob` of a detection is then the fusion of the objectness and of the class probability, see `:score_combine`.
+      - `:objectness_classes` (list of ranges, default: nil) — With `objectness: true`, the ranges of the classes gated by the objectness, like `[0..79]` for the detection classes of a hybrid head sharing the tensor with attribute classes. The other classes use their raw class probability. All the classes are gated by default. The class of a detection is still its most probable raw class.
+      - `:score_combine` (`:product`, `:geometric_mean` or `{:weighted, obj_weight}`, default: `:product`) — With `objectness: true`, how the objectness and the class probability are fused into `prob`: `objectness * class_prob` (YOLOv5), `sqrt(objectness * class_prob)`, or `obj_weight * objectness + (1 - obj_weight) * class_prob` with `obj_weight` between 0 and 1. The class is still the argmax of the class probabilities.
+      - `:score_type` (`:sigmoid` or `:softmax`, default: `:sigmoid`) — How the model computes the class probabilities: independent per-class sigmoids (like YOLO, several classes can be high at the same time) or a softmax (the probabilities sum to 1 and the argmax is unambiguous).
+      - `:margin_threshold` (float, default: 0.0) — Drops the candidates whose best class probability doesn't exceed the second best by at least this margin. Not applied with `multi_label: true`, where several high classes are expected.
+      - `:class_tie_epsilon` (float, default: nil) — Drops the candidates whose two best class probabilities are within this epsilon (`0.0` for the exact ties), the uncertain rows where the argmax would pick the first class arbitrarily. Not applied with `multi_label: true`. `nil` keeps them.
+      - `:multi_label` (boolean, default: false) — Only with `score_type: :sigmoid`. Each class above `:prob_threshold` becomes a separate candidate, instead of taking only the best class of each row.
+      - `:format` (`:list`, `:map`, `:boxes`, `:binary`, `:flat` or `:yolo_label`, default: `:list`) — Whether each detection is returned as a list `[cx, cy, w, h, prob, class_idx]` or as a map with the same keys (`class_idx` as an integer). The map can carry the extra keys requested with the options below. `:boxes` returns only the box `[cx, cy, w, h]` (plus the angle with `obb: true`), for the localization-only consumers. `:binary` returns the list rows packed as f32, `%{data: binary, count: count, columns: 6}` (7 columns with `obb: true`), to build a `{count, columns}` tensor with `Nx.from_binary/2` and `Nx.reshape/2`. `:flat` returns the same rows interleaved in a single list, `%{data: [cx1, cy1, w1, h1, prob1, class_idx1, cx2, ...], count: count, columns: 6}`, cheaper to build than the nested lists. `:yolo_label` returns the rows of the YOLO label files, `[class_idx, cx, cy, w, h]` with the class first (an integer) and the coordinates normalized by the `:image_size`, to write pseudo-labels from the inference; the model coordinates are normalized, without `:letterbox`, the offsets and `:scale`. It requires `:image_size` and is not supported with `obb: true`.
+      - `:endianness` (`:native`, `:little` or `:big`, default: `:native`) — Byte order of the floats of `format: :binary`. The native order is the one of `Nx.from_binary/2`, `:little` and `:big` are for the consumers on other hosts, like a service receiving the detections over the wire.
+      - `:image_size` (`{width, height}`, default: nil) — Size of the image in the model coordinates, used by `:clamp`, `:prior_grid` and `:oversize_policy`.
+      - `:prior_grid` (tensor, default: nil) — Spatial prior of a fixed camera: a coarse `{grid_height, grid_width}` tensor of multipliers over the `:image_size` image. The `prob` of each detection is multiplied by the weight of the cell of its center before the `:prob_threshold` filter, to boost or penalize some regions (like the road area). The centers out of the image have weight 1.0. Requires `:image_size`.
+      - `:min_size` (number, default: 0.0) — Minimum width and height of the detections, in the model coordinates. The smaller boxes are dropped before the NMS.
+      - `:class_min_sizes` (map, default: nil) — Map of `class_idx => min_size` overriding `:min_size` for some classes, since the plausible sizes depend on the class: a 10 pixels "person" is noise, a 10 pixels "bird" isn't. The classes not in the map use `:min_size`.
+      - `:area_weights` (list of `{max_area, weight}`, default: nil) — Size-based confidence adjustment, like boosting the small objects of an aerial dataset that have systematically lower probabilities. A piecewise function of the box area (`w * h` in the model coordinates): the `prob` of each detection is multiplied by the `weight` of the first step with the area within its `max_area`, before the `:prob_threshold` filter. The boxes larger than all the steps keep their `prob`. For example `[{100, 1.5}, {400, 1.2}]` boosts the boxes up to 10x10 by 1.5 and the ones up to 20x20 by 1.2. `nil` for no adjustment.
+      - `:calibration` (list of `{input, output}`, default: nil) — Calibration curve of the scores, like an isotonic regression fit offline: breakpoints sorted by `input`, the `prob` of each detection (after `:score_combine`) is remapped by the linear interpolation between them, before the weights and the `:prob_threshold` filter. The scores below the first `input` (above the last one) take the first (last) `output`. For example `[{0.0, 0.0}, {0.5, 0.3}, {1.0, 1.0}]` lowers the scores below 0.5 more than the high ones. `nil` for the raw scores.
+      - `:oversize_policy` (`:keep`, `:clamp` or `:drop`, default: `:keep`) — What to do with the boxes wider or taller than the `:image_size` image, often decoding errors that distort the IoU: `:keep` them, `:clamp` their `w` and `h` to the image size (the center is kept), or `:drop` them. Applied when parsing, before the NMS. `:clamp` and `:drop` require `:image_size`.
+      - `:clamp` (boolean, default: false) — Whether to move the edges of the returned boxes inside the `:image_size` image. Applied after the NMS and before `:scale`. Not supported with `obb: true`.
+      - `:metadata` (boolean, default: false) — Whether to return `{detections, metadata}`, with `metadata` a map of:
+        - `:class_counts` — `%{class_idx => count}` of the returned detections.
+        - `:dropped_rows` — The number of malformed rows skipped, see `:parse_mode`.
+        - `:suppressed` — With `suppressed: true`, the boxes removed by the NMS.
+        - `:class_mapping` — With `relabel: true`, `%{class_idx => original_class_idx}`.
+        - `:fingerprint` — With `fingerprint: true`, a 64-bit hash of the detections.
+        - `:transpose` — With `auto_transpose: true`, the chosen orientation.
+        - `:coverage_area`, `:coverage_fraction` — With `coverage: true`, the covered area.
+        - `:truncated` — With `:candidate_budget`, whether the candidates were over the budget.
+        - `:duplicate_rows` — With `duplicate_rows: true`, the number of repeated rows.
+        - `:class_centroids` — With `class_centroids: true`, `%{class_idx => {cx, cy}}`.
+      - `:fingerprint` (boolean, default: false) — Adds to the metadata a stable 64-bit hash of the returned detections, to key a cache on their content. It doesn't depend on the order of the detections, and is computed over the model coordinates (integers) and the `prob` rounded to 0.001, so the same boxes give the same fingerprint across runs. Requires `metadata: true`.
+      - `:coverage` (boolean, default: false) — Adds to the metadata the `:coverage_area`, the area covered by the union of the returned boxes (the overlaps counted once), in their output coordinates (after `:clamp`, `:letterbox`, the offsets and `:scale`), and with `:image_size` the `:coverage_fraction` of the image it covers. It's a measure of how busy the scene is. Requires `metadata: true` and is not supported with `obb: true`.
+      - `:duplicate_rows` (boolean, default: false) — Adds to the metadata the number of rows identical to a previous row of the tensor (all the features, bit for bit), a symptom of an upstream bug like a tile concatenated twice. It's a diagnostic, the duplicates are still parsed and suppressed by the NMS like any other row. Requires `metadata: true`.
+      - `:class_centroids` (boolean, default: false) — Adds to the metadata the centroid of the returned boxes of each class, the mean of their centers weighted by their `prob`, in the output coordinates of the returned boxes, to see where the detections of a class are concentrated (like for a heatmap or a clustering) without scanning them again. Requires `metadata: true`.
+      - `:suppressed` (boolean, default: false) — Debug flag to explain the NMS decisions: adds the boxes removed by `method: :hard` or `:soft` to the metadata, as maps with the `:suppressed_by` key (the index in the detections of the kept box that suppressed it, the one overlapping it the most with `:hard`) and the triggering `:iou`. Requires `metadata: true` and is not supported with `group_by_class: true`.
+      - `:letterbox` (`{gain, pad_x, pad_y}`, default: nil) — The letterbox resize of the model input, to return the boxes in the coordinates of the original image: `cx`, `cy` are unpadded and `cx`, `cy`, `w`, `h` divided by the `gain`. Applied after the NMS and `:clamp`, before `:scale`. For `run_batch/2` it can be a list with the letterbox of each image.
+      - `:offset_x`, `:offset_y` (number, default: 0.0) — Offset added to the `cx`, `cy` of the returned detections, like the position of the tile in the large image for the tiled inference, to get the boxes in the global coordinates. Applied after `:letterbox`, before `:scale`. The NMS doesn't see it, use `merge/3` to suppress the duplicates across overlapping tiles.
+      - `:scale` (float, default: 1.0) — Factor multiplying the `cx`, `cy`, `w`, `h` of the returned detections, to get the boxes in another coordinate system (like a downscaled thumbnail). Must be positive. Applied after the NMS, which always works on the model coordinates.
+      - `:group_by_class` (boolean, default: false) — Whether to return a map `%{class_idx => detections}` instead of a flat list.
+      - `:class_names` (list of strings, default: nil) — Names of the classes, indexed by `class_idx`. Adds the `:label` key with the name of the class, `nil` for the classes out of the list. Pass it once to `handle_new/2` or `stream_new/2` to not send the names on every frame. Requires `format: :map`.
+      - `:corners` (boolean, default: false) — Adds the corners of the box, the `:x1`, `:y1`, `:x2` and `:y2` keys, next to its center and size: the center to place a label and the corners to draw the rectangle, without computing them for every box. They are in the same coordinates of `cx`, `cy`, `w`, `h`. Not supported with `obb: true`. Requires `format: :map`.
+      - `:row_index` (boolean, default: false) — Adds the `:row_index` key with the index of the detection in the original tensor (across all the chunks for the stream API), to correlate a detection back to its raw row. Requires `format: :map`.
+      - `:clamped` (boolean, default: false) — Adds the `:clamped` key, `true` when `:clamp` changed the box (a detection cut by the image border). Requires `format: :map`.
+      - `:display` (boolean, default: false) — Adds the `:display` key, `true` when the `prob` of the detection is at least `:display_threshold`. It lets the NMS run at a low `:prob_threshold` (to feed a tracker, for example) and still tell the curated subset to show. Requires `format: :map`.
+      - `:display_threshold` (float, default: nil) — Threshold of the `:display` key, `nil` for `:prob_threshold` (all the detections flagged).
+      - `:class_probs` (boolean, default: false) — Adds the `:class_probs` key with the list of all the class probabilities of the kept box. Requires `format: :map`.
+      - `:top_k_classes` (integer, default: 1) — With k > 1, adds the `:top_classes` key with the `k` most probable classes of the row of the kept box, as `{class_idx, prob}` tuples by `prob` descending, for the ambiguity-aware stages like a re-ranker. The probabilities are the raw class probabilities and the classes are not relabeled. Requires `format: :map` when k > 1.
+      - `:class_scores` (list of integers, default: nil) — Adds the `:class_scores` key, `%{class_idx => score}` with the probabilities of these classes in the row of the kept box, whatever its class, like the score of the ground-truth class for the class-conditional metrics. The scores are the raw class probabilities, before the objectness and the weights, and the classes are the ones of the model (not relabeled). The classes out of the class probabilities are missing. Requires `format: :map`.
+      - `:palette_size` (integer, default: nil) — Adds the `:color_index` key, `class_idx % palette_size`, the index of the color of the box in an overlay palette of that size, the same for all the boxes of a class across the frames. Requires `format: :map`.
 
   ## Returns
 
-    - A list of lists `[cx, cy, w, h, prob, class_idx]` for each detected object, where:
+    - A list of lists `[cx, cy, w, h, prob, class_idx]` for each detected object (or maps with `format: :map`, grouped by class with `group_by_class: true`), where:
       - `cx`, `cy`: Center coordinates of the bounding box.
       - `w`, `h`: Width and height of the bounding box.
       - `prob`: Confidence score (0..1).
       - `class_idx`: Index of the detected class.
+
+    Without detections the result is `[]` (`%{}` with `group_by_class: true`) for `:list`, `:map` and `:boxes`,
+    and `%{data: "", count: 0, columns: 6}` for `:binary` (`data: []` for `:flat`), which still reshapes to a `{0, 6}` tensor.
   """
-  @spec run(Nx.Tensor.t(), options :: keyword()) :: [[float()]]
+  @spec run(Nx.Tensor.t(), options :: keyword()) ::
+          [[float()]] | [map()] | map() | {[[float()]] | [map()] | map(), map()}
   def run(%Nx.Tensor{} = tensor, options) do
-    options = Keyword.merge(@default_options, options)
-    iou_threshold = Keyword.get(options, :iou_threshold, @default_options[:iou_threshold])
-    prob_threshold = Keyword.get(options, :prob_threshold, @default_options[:prob_threshold])
-    transpose = Keyword.get(options, :transpose, @default_options[:transpose])
+    {rows, columns} =
+      case Nx.shape(tensor) do
+        {1, rows, columns} -> {rows, columns}
+        {rows, columns} -> {rows, columns}
+        _ -> raise "Invalid tensor shape"
+      end
+
+    tensor
+    |> Nx.to_binary()
+    |> run_nif(rows, columns, nif_options(orient(options, rows, columns)))
+  end
+
+  @doc """
+  Runs NMS like `run/2` but returns only the counts of the boxes, without building the detections.
+
+  It's a cheap way to measure the suppression ratio, like when sweeping `:iou_threshold` over many frames.
+
+  ## Parameters
+
+    - `tensor`: The model output, as in `run/2`.
+    - `options`: The options accepted by `run/2`, the output options are ignored.
 
+  ## Returns
+
+    - `{raw_count, filtered_count, kept_count}`: the parsed candidates, the candidates above
+      `:prob_threshold` and the detections returned by `run/2`.
+  """
+  @spec counts(Nx.Tensor.t(), options :: keyword()) ::
+          {non_neg_integer(), non_neg_integer(), non_neg_integer()}
+  def counts(%Nx.Tensor{} = tensor, options \\ []) do
     {rows, columns} =
       case Nx.shape(tensor) do
         {1, rows, columns} -> {rows, columns}
@@ -56,7 +300,110 @@ defmodule YoloFastNMS do
 
     tensor
     |> Nx.to_binary()
-    |> run_with_binary(prob_threshold, iou_threshold, rows, columns, transpose)
+    |> counts_nif(rows, columns, nif_options(orient(options, rows, columns)))
+  end
+
+  @doc false
+  def counts_nif(_tensor_binary, _rows, _columns, _options),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Runs NMS on each image of a batched `{batch, rows, columns}` tensor.
+
+  ## Parameters
+
+    - `tensor`: The batched model output.
+    - `options`: The options accepted by `run/2`, applied to every image. `:letterbox` can also be a list
+      with a `{gain, pad_x, pad_y}` for each image, for the batches of images resized with different gains;
+      its length must match the batch size. A single `:letterbox` is shared by all the images.
+
+  ## Returns
+
+    - A list with the result of `run/2` for each image, in the batch order.
+  """
+  @spec run_batch(Nx.Tensor.t(), options :: keyword()) :: list()
+  def run_batch(%Nx.Tensor{} = tensor, options \\ []) do
+    {batch, _rows, _columns} = batch_shape(tensor)
+    run_images(tensor, 0, batch, options)
+  end
+
+  @doc """
+  Runs NMS on the `count` images of a batched tensor from the `cursor` one, like `run_batch/2` over a
+  sub-range of the batch.
+
+  For the huge batches, it returns the results incrementally instead of building a single giant term:
+  each call parses and encodes only the images of its chunk.
+
+  ## Parameters
+
+    - `tensor`: The batched `{batch, rows, columns}` model output.
+    - `cursor`: The index of the first image of the chunk, `0` for the first call.
+    - `count`: The maximum number of images of the chunk, the last one can be shorter.
+    - `options`: The options accepted by `run_batch/2`. A list of `:letterbox` is of the whole batch,
+      each image of the chunk takes its own.
+
+  ## Returns
+
+    - `{results, next_cursor}`, with `results` the result of `run/2` for each image of the chunk and
+      `next_cursor` the cursor of the next chunk, `nil` after the last image.
+  """
+  @spec run_batch_chunk(Nx.Tensor.t(), non_neg_integer(), pos_integer(), options :: keyword()) ::
+          {list(), non_neg_integer() | nil}
+  def run_batch_chunk(%Nx.Tensor{} = tensor, cursor, count, options \\ [])
+      when is_integer(cursor) and cursor >= 0 and is_integer(count) and count > 0 do
+    {batch, _rows, _columns} = batch_shape(tensor)
+
+    if cursor > batch do
+      raise ArgumentError, "cursor #{cursor} is out of a batch of #{batch} images"
+    end
+
+    count = min(count, batch - cursor)
+    next_cursor = if cursor + count < batch, do: cursor + count
+
+    {run_images(tensor, cursor, count, options), next_cursor}
+  end
+
+  defp batch_shape(tensor) do
+    case Nx.shape(tensor) do
+      {batch, rows, columns} -> {batch, rows, columns}
+      shape -> raise ArgumentError, "expected a {batch, rows, columns} tensor, got: #{inspect(shape)}"
+    end
+  end
+
+  # The results of the `count` images from the `first` one, only the binary of these images is built.
+  defp run_images(tensor, first, count, options) do
+    {batch, rows, columns} = batch_shape(tensor)
+
+    letterboxes =
+      case Keyword.get(options, :letterbox) do
+        letterboxes when is_list(letterboxes) and length(letterboxes) == batch ->
+          Enum.slice(letterboxes, first, count)
+
+        letterboxes when is_list(letterboxes) ->
+          raise ArgumentError,
+                "letterbox has #{length(letterboxes)} entries for a batch of #{batch} images"
+
+        letterbox ->
+          List.duplicate(letterbox, count)
+      end
+
+    binary =
+      cond do
+        count == batch -> Nx.to_binary(tensor)
+        count == 0 -> <<>>
+        true -> tensor |> Nx.slice_along_axis(first, count, axis: 0) |> Nx.to_binary()
+      end
+
+    image_size = rows * columns * 4
+    options = orient(options, rows, columns)
+
+    letterboxes
+    |> Enum.with_index()
+    |> Enum.map(fn {letterbox, i} ->
+      binary
+      |> binary_part(i * image_size, image_size)
+      |> run_nif(rows, columns, nif_options(Keyword.put(options, :letterbox, letterbox)))
+    end)
   end
 
   @doc """
@@ -71,6 +418,11 @@ defmodule YoloFastNMS do
   - `rows` - Number of rows in the tensor
   - `columns` - Number of columns in the tensor
   - `transpose` - Whether to transpose the input tensor
+  - `options` - Any other option accepted by `run/2`, and:
+    - `:byte_offset` (integer, default: 0) — Offset in bytes of the tensor in `tensor_binary`, like the payload
+      after the header of a framed buffer, so it's read in place without slicing (and copying) the binary.
+      With an offset the bytes after the tensor are ignored, the tensor must be within the binary. Also
+      accepted by `handle_new/2` for the binaries given to `handle_run/3`.
 
   Returns a list of lists `[cx, cy, w, h, prob, class_idx]` where:
   - `cx`, `cy`: Center position coordinates of the detected object
@@ -84,9 +436,895 @@ defmodule YoloFastNMS do
           iou_threshold :: float(),
           rows :: integer(),
           columns :: integer(),
-          transpose :: boolean()
+          transpose :: boolean(),
+          options :: keyword()
         ) :: [[float()]]
-  def run_with_binary(tensor_binary, _prob_threshold, _iou_threshold, _rows, _columns, _transpose)
-      when is_binary(tensor_binary),
-      do: :erlang.nif_error(:nif_not_loaded)
+  def run_with_binary(
+        tensor_binary,
+        prob_threshold,
+        iou_threshold,
+        rows,
+        columns,
+        transpose,
+        options \\ []
+      )
+      when is_binary(tensor_binary) do
+    options =
+      Keyword.merge(options,
+        prob_threshold: prob_threshold,
+        iou_threshold: iou_threshold,
+        transpose: transpose
+      )
+
+    run_nif(tensor_binary, rows, columns, nif_options(options))
+  end
+
+  @doc false
+  def run_nif(_tensor_binary, _rows, _columns, _options),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Runs NMS like `run/2` across the outputs of the detection heads of a multi-scale model exported
+  separately (like the three scales of YOLO), without concatenating them first: the candidates of
+  all the tensors are suppressed together, the same as `run/2` on their concatenation along the
+  detections axis.
+
+  ## Parameters
+
+    - `tensors`: The list of the model outputs, one for each scale, with the shapes of `run/2`.
+      They must have the same number of features (the columns, or the rows with `transpose: true`).
+    - `options`: The options accepted by `run/2`, except the ones of a single tensor: `:row_offset`,
+      `:row_count`, `:byte_offset`, `:auto_transpose`, `:sanity_check` and `:duplicate_rows`.
+
+  ## Returns
+
+    - The detections, as returned by `run/2`. The `:row_index` is the index of the detection in
+      the concatenation of the tensors.
+  """
+  @spec run_scales([Nx.Tensor.t()], options :: keyword()) ::
+          [[float()]] | [map()] | map() | {[[float()]] | [map()] | map(), map()}
+  def run_scales([_ | _] = tensors, options \\ []) do
+    shapes =
+      Enum.map(tensors, fn tensor ->
+        case Nx.shape(tensor) do
+          {1, rows, columns} -> {rows, columns}
+          {rows, columns} -> {rows, columns}
+          _ -> raise "Invalid tensor shape"
+        end
+      end)
+
+    unsupported = [
+      :row_offset,
+      :row_count,
+      :byte_offset,
+      :auto_transpose,
+      :sanity_check,
+      :duplicate_rows
+    ]
+
+    if key = Enum.find(unsupported, &Keyword.has_key?(options, &1)) do
+      raise ArgumentError, "run_scales/2 doesn't support #{inspect(key)}"
+    end
+
+    options = nif_options(options)
+    features =
+      Enum.map(shapes, fn {rows, columns} -> if options.transpose, do: rows, else: columns end)
+
+    unless features |> Enum.uniq() |> length() == 1 do
+      raise ArgumentError, "the scales have a different number of features: #{inspect(features)}"
+    end
+
+    tensors
+    |> Enum.map(&Nx.to_binary/1)
+    |> run_scales_nif(shapes, options)
+  end
+
+  @doc false
+  def run_scales_nif(_tensor_binaries, _shapes, _options),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Runs NMS like `run/2` on a model output split in two tensors, the boxes as f32 and the class
+  scores quantized as u8 (`0..255` for `0.0..1.0`), a quarter of the size of the f32 scores.
+
+  The scores are dequantized dividing them by 255 before the argmax and `:prob_threshold`.
+
+  ## Parameters
+
+    - `boxes`: An `{:f, 32}` tensor with the `cx, cy, w, h` of each detection (followed by the
+      objectness with `objectness: true`), with shape `{rows, 4}`, or `{4, rows}` with `transpose: true`.
+    - `scores`: A `{:u, 8}` tensor with the class scores of each detection, with shape `{rows, classes}`,
+      or `{classes, rows}` with `transpose: true`.
+    - `options`: The options accepted by `run/2`. `obb: true` and `argmaxed: true` are not supported,
+      nor the window and padding options `:row_offset`, `:row_count`, `:byte_offset` and `:valid_columns`,
+      `:auto_transpose` and `:group_column`, which raise an `ArgumentError`. The boxes must have exactly
+      the 4 box features and the objectness, and `:score_column` and `:class_column` index the box
+      features followed by the class scores.
+
+  ## Returns
+
+    - The detections, as returned by `run/2`.
+  """
+  @spec run_quantized(Nx.Tensor.t(), Nx.Tensor.t(), options :: keyword()) ::
+          [[float()]] | [map()] | map() | {[[float()]] | [map()] | map(), map()}
+  def run_quantized(%Nx.Tensor{} = boxes, %Nx.Tensor{} = scores, options \\ []) do
+    unless Nx.type(boxes) == {:f, 32} and Nx.type(scores) == {:u, 8} do
+      raise ArgumentError,
+            "expected {:f, 32} boxes and {:u, 8} scores, " <>
+              "got: #{inspect(Nx.type(boxes))} and #{inspect(Nx.type(scores))}"
+    end
+
+    unsupported = [
+      :row_offset,
+      :row_count,
+      :byte_offset,
+      :valid_columns,
+      :auto_transpose,
+      :group_column
+    ]
+
+    if key = Enum.find(unsupported, &Keyword.has_key?(options, &1)) do
+      raise ArgumentError, "run_quantized/3 doesn't support #{inspect(key)}"
+    end
+
+    options = nif_options(options)
+
+    if options.obb or options.argmaxed do
+      raise ArgumentError, "run_quantized/3 doesn't support obb: true and argmaxed: true"
+    end
+
+    {boxes, scores} =
+      if options.transpose,
+        do: {Nx.transpose(boxes), Nx.transpose(scores)},
+        else: {boxes, scores}
+
+    {{rows, box_columns}, {score_rows, _classes}} = {Nx.shape(boxes), Nx.shape(scores)}
+
+    unless rows == score_rows do
+      raise ArgumentError,
+            "boxes and scores have a different number of detections: #{rows} and #{score_rows}"
+    end
+
+    run_quantized_nif(
+      Nx.to_binary(boxes),
+      Nx.to_binary(scores),
+      rows,
+      box_columns,
+      %{options | transpose: false}
+    )
+  end
+
+  @doc false
+  def run_quantized_nif(_boxes_binary, _scores_binary, _rows, _box_columns, _options),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Checks that a binary of `binary_len` bytes holds a `{rows, columns}` tensor of type `dtype`
+  accepted by `run_with_binary/7`, without running the NMS.
+
+  It's a cheap check to run at startup, so a misconfigured model export fails before the first frame.
+
+  ## Parameters
+
+    - `binary_len`: The size in bytes of the tensor binary, like `byte_size(Nx.to_binary(tensor))`.
+    - `rows`, `columns`: The expected shape.
+    - `dtype`: The Nx type of the tensor, only `{:f, 32}` is supported.
+
+  ## Returns
+
+    - `:ok`, or `{:error, reason}` with a message describing the mismatch.
+  """
+  @spec validate(
+          binary_len :: non_neg_integer(),
+          rows :: non_neg_integer(),
+          columns :: non_neg_integer(),
+          dtype :: Nx.Type.t()
+        ) :: :ok | {:error, String.t()}
+  def validate(binary_len, rows, columns, dtype \\ {:f, 32})
+      when is_integer(binary_len) and is_integer(rows) and is_integer(columns) do
+    validate_nif(binary_len, rows, columns, dtype)
+  end
+
+  @doc false
+  def validate_nif(_binary_len, _rows, _columns, _dtype), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Returns the build configuration of the loaded NIF, to check at runtime that the right artifact
+  is deployed, like a build with the `parallel` feature.
+
+  ## Returns
+
+    - A map with the `:version` of the native crate, `:debug` (whether it's a debug build, with the
+      debug checks) and the `:features` it was compiled with, `%{alloc_count: boolean, parallel: boolean,
+      threads: boolean}`.
+  """
+  @spec info() :: %{version: String.t(), debug: boolean(), features: %{atom() => boolean()}}
+  def info, do: info_nif()
+
+  @doc false
+  def info_nif, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Computes the Intersection over Union (IoU) of two bounding boxes.
+
+  It's the same implementation used by the NMS, so evaluation metrics computed with it match the post-processing.
+  Like the NMS, the box parameters are rounded to integers.
+
+  ## Parameters
+
+    - `a`, `b`: Bounding boxes as `[cx, cy, w, h]` lists, or `[cx, cy, w, h, angle]` for oriented boxes (angle in radians), using the rotated IoU of `obb: true`.
+    - `options`: Keyword list of options:
+      - `:pixel_inclusive` (boolean, default: false) — The edges convention, as in `run/2`.
+      - `:precision` (`:f32`, `:f64` or `:fixed`, default: `:f32`) — The IoU arithmetic, as in `run/2`. With `:f64` the returned IoU isn't rounded to f32, with `:fixed` it's a multiple of 1/1024.
+      - `:overlap_metric` (`:iou`, `:iomin` or `:iomax`, default: `:iou`) — The overlap measure, as in `run/2`.
+      - `:iou_eps` (number, default: 0.0) — Epsilon added to the denominator, as in `run/2`.
+      - `:zero_union_iou` (`:zero` or `:one`, default: `:zero`) — The IoU of two identical zero-area boxes, as in `run/2`.
+
+  ## Returns
+
+    - The IoU as a float between 0 and 1.
+  """
+  @spec iou(a :: [number()], b :: [number()], options :: keyword()) :: float()
+  def iou(a, b, options \\ []) when is_list(a) and is_list(b) do
+    iou_nif(Enum.map(a, &(&1 / 1)), Enum.map(b, &(&1 / 1)), nif_options(options))
+  end
+
+  @doc false
+  def iou_nif(_a, _b, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Merges two lists of detections returned by `run/2`, like the results of two configurations with different thresholds for different classes, running a final NMS to remove the duplicates across the lists.
+
+  ## Parameters
+
+    - `a`, `b`: Lists of detections in the default list format, `[cx, cy, w, h, prob, class_idx]` (plus the angle with `obb: true`).
+    - `options`: The NMS and output options accepted by `run/2`. The suppression is agnostic by default (`agnostic: true`), the duplicates across lists often have different classes. The detections are not filtered again by `:prob_threshold`.
+
+  ## Returns
+
+    - The merged detections, in the format given by the options.
+  """
+  @spec merge(a :: [[number()]], b :: [[number()]], options :: keyword()) ::
+          [[float()]] | [map()] | map()
+  def merge(a, b, options \\ []) when is_list(a) and is_list(b) do
+    detections = Enum.map(a ++ b, fn detection -> Enum.map(detection, &(&1 / 1)) end)
+
+    merge_nif(detections, nif_options(Keyword.put_new(options, :agnostic, true)))
+  end
+
+  @doc false
+  def merge_nif(_detections, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Packs detections back into a model output binary, the inverse of the parsing of `run_with_binary/7`,
+  to build precise fixtures or replay recorded detections.
+
+  ## Parameters
+
+    - `detections`: Detections in the default list format, `[cx, cy, w, h, prob, class_idx]` (plus the angle
+      with `obb: true`).
+    - `classes_count`: The number of class probabilities of each row.
+    - `options`: Keyword list of options:
+      - `:transpose` (boolean, default: true) — Whether to lay out the binary as `{columns, rows}`, like the YOLOv8 output.
+      - `:objectness` (boolean, default: false) — Whether to add the objectness column (YOLOv5), set to 1.0.
+      - `:obb` (boolean, default: false) — Whether the detections have the angle, added as the last column.
+
+  ## Returns
+
+    - `{binary, rows, columns}`: the f32 binary and its shape, to pass to `run_with_binary/7` with the same
+      options. Each row has the box, the objectness and the class probabilities, all 0.0 but `prob` in the
+      `class_idx` column.
+  """
+  @spec pack([[number()]], pos_integer(), options :: keyword()) ::
+          {binary(), non_neg_integer(), non_neg_integer()}
+  def pack(detections, classes_count, options \\ []) when is_list(detections) do
+    options = nif_options(options)
+    detections = Enum.map(detections, fn detection -> Enum.map(detection, &(&1 / 1)) end)
+    extra_features = Enum.count([options.objectness, options.obb], & &1)
+    features = 4 + classes_count + extra_features
+    binary = pack_nif(detections, classes_count, options)
+
+    if options.transpose,
+      do: {binary, features, length(detections)},
+      else: {binary, length(detections), features}
+  end
+
+  @doc false
+  def pack_nif(_detections, _classes_count, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  # The NIF decodes the options as a map with all the keys of `@default_options`.
+  defp nif_options(options) do
+    options =
+      @default_options
+      |> Keyword.merge(options)
+      |> Keyword.take(Keyword.keys(@default_options))
+      |> Map.new()
+
+    unless is_number(options.prob_threshold) and options.prob_threshold >= 0 do
+      raise ArgumentError,
+            "prob_threshold must be a non-negative number, got: #{inspect(options.prob_threshold)}"
+    end
+
+    unless is_number(options.iou_threshold) and options.iou_threshold >= 0 and
+             options.iou_threshold <= 1 do
+      raise ArgumentError,
+            "iou_threshold must be a number between 0 and 1, got: #{inspect(options.iou_threshold)}"
+    end
+
+    unless options.match_tolerance == nil or
+             (is_number(options.match_tolerance) and options.match_tolerance >= 0) do
+      raise ArgumentError,
+            "match_tolerance must be a non-negative number, got: #{inspect(options.match_tolerance)}"
+    end
+
+    unless options.force_keep_threshold == nil or is_number(options.force_keep_threshold) do
+      raise ArgumentError,
+            "force_keep_threshold must be a number, got: #{inspect(options.force_keep_threshold)}"
+    end
+
+    for key <- [:band_low, :band_high] do
+      unless options[key] == nil or is_number(options[key]) do
+        raise ArgumentError, "#{key} must be a number, got: #{inspect(options[key])}"
+      end
+    end
+
+    if options.band_low != nil and options.band_high != nil and
+         options.band_low > options.band_high do
+      raise ArgumentError,
+            "band_low must not be greater than band_high, " <>
+              "got: #{inspect(options.band_low)} and #{inspect(options.band_high)}"
+    end
+
+    unless options.min_center_distance == nil or
+             (is_number(options.min_center_distance) and options.min_center_distance >= 0) do
+      raise ArgumentError,
+            "min_center_distance must be a non-negative number, " <>
+              "got: #{inspect(options.min_center_distance)}"
+    end
+
+    unless options.grid_max == nil or (is_number(options.grid_max) and options.grid_max > 0) do
+      raise ArgumentError, "grid_max must be a positive number, got: #{inspect(options.grid_max)}"
+    end
+
+    unless is_number(options.sigma) and options.sigma > 0 do
+      raise ArgumentError, "sigma must be a positive number, got: #{inspect(options.sigma)}"
+    end
+
+    unless options.class_sigmas == nil or
+             (is_map(options.class_sigmas) and
+                Enum.all?(Map.values(options.class_sigmas), &(is_number(&1) and &1 > 0))) do
+      raise ArgumentError,
+            "class_sigmas must be a map of positive numbers, got: #{inspect(options.class_sigmas)}"
+    end
+
+    unless is_number(options.scale) and options.scale > 0 do
+      raise ArgumentError, "scale must be a positive number, got: #{inspect(options.scale)}"
+    end
+
+    unless is_number(options.proximity_threshold) and options.proximity_threshold >= 0 do
+      raise ArgumentError,
+            "proximity_threshold must be a non-negative number, " <>
+              "got: #{inspect(options.proximity_threshold)}"
+    end
+
+    unless is_number(options.dedup_tolerance) and options.dedup_tolerance >= 0 do
+      raise ArgumentError,
+            "dedup_tolerance must be a non-negative number, got: #{inspect(options.dedup_tolerance)}"
+    end
+
+    unless is_number(options.margin_threshold) and options.margin_threshold >= 0 do
+      raise ArgumentError,
+            "margin_threshold must be a non-negative number, got: #{inspect(options.margin_threshold)}"
+    end
+
+    unless options.class_priorities == nil or
+             (is_map(options.class_priorities) and
+                Enum.all?(Map.values(options.class_priorities), &(is_number(&1) and &1 > 0))) do
+      raise ArgumentError,
+            "class_priorities must be a map of positive numbers, " <>
+              "got: #{inspect(options.class_priorities)}"
+    end
+
+    options = %{
+      options
+      | prob_threshold: options.prob_threshold / 1,
+        iou_threshold: options.iou_threshold / 1,
+        radius: options.radius / 1,
+        offset_x: options.offset_x / 1,
+        iou_eps: options.iou_eps / 1,
+        min_size: options.min_size / 1,
+        class_min_sizes:
+          options.class_min_sizes &&
+            Map.new(options.class_min_sizes, fn {class, size} -> {class, size / 1} end),
+        temporal_iou: options.temporal_iou && options.temporal_iou / 1,
+        support_iou: options.support_iou && options.support_iou / 1,
+        match_tolerance: options.match_tolerance && options.match_tolerance / 1,
+        min_center_distance: options.min_center_distance && options.min_center_distance / 1,
+        force_keep_threshold: options.force_keep_threshold && options.force_keep_threshold / 1,
+        band_low: options.band_low && options.band_low / 1,
+        band_high: options.band_high && options.band_high / 1,
+        grid_max: options.grid_max && options.grid_max / 1,
+        class_tie_epsilon: options.class_tie_epsilon && options.class_tie_epsilon / 1,
+        offset_y: options.offset_y / 1,
+        display_threshold: options.display_threshold && options.display_threshold / 1,
+        class_priorities:
+          options.class_priorities &&
+            Map.new(options.class_priorities, fn {class, weight} -> {class, weight / 1} end),
+        margin_threshold: options.margin_threshold / 1,
+        dedup_tolerance: options.dedup_tolerance / 1,
+        proximity_threshold: options.proximity_threshold / 1,
+        scale: options.scale / 1,
+        sigma: options.sigma / 1,
+        class_sigmas:
+          options.class_sigmas &&
+            Map.new(options.class_sigmas, fn {class, sigma} -> {class, sigma / 1} end)
+    }
+
+    unless is_integer(options.byte_offset) and options.byte_offset >= 0 do
+      raise ArgumentError,
+            "byte_offset must be a non-negative integer, got: #{inspect(options.byte_offset)}"
+    end
+
+    unless is_integer(options.top_k_classes) and options.top_k_classes > 0 do
+      raise ArgumentError,
+            "top_k_classes must be a positive integer, got: #{inspect(options.top_k_classes)}"
+    end
+
+    unless options.palette_size == nil or
+             (is_integer(options.palette_size) and options.palette_size > 0) do
+      raise ArgumentError,
+            "palette_size must be a positive integer, got: #{inspect(options.palette_size)}"
+    end
+
+    unless is_integer(options.threads) and options.threads > 0 do
+      raise ArgumentError, "threads must be a positive integer, got: #{inspect(options.threads)}"
+    end
+
+    options =
+      case options.letterbox do
+        nil ->
+          options
+
+        {gain, pad_x, pad_y}
+        when is_number(gain) and gain > 0 and is_number(pad_x) and is_number(pad_y) ->
+          %{options | letterbox: {gain / 1, pad_x / 1, pad_y / 1}}
+
+        letterbox ->
+          raise ArgumentError,
+                "letterbox must be {gain, pad_x, pad_y} with a positive gain, got: #{inspect(letterbox)}"
+      end
+
+    options =
+      case options.score_combine do
+        {:weighted, obj_weight}
+        when is_number(obj_weight) and obj_weight >= 0 and obj_weight <= 1 ->
+          %{options | score_combine: {:weighted, obj_weight / 1}}
+
+        combine when combine in [:product, :geometric_mean] ->
+          options
+
+        combine ->
+          raise ArgumentError,
+                "score_combine must be :product, :geometric_mean or {:weighted, obj_weight} " <>
+                  "with obj_weight between 0 and 1, got: #{inspect(combine)}"
+      end
+
+    options =
+      case {options.prior_grid, options.image_size} do
+        {nil, _image_size} ->
+          options
+
+        # already converted, like the options of a handle
+        {{_grid_height, _grid_width, weights}, _image_size} when is_list(weights) ->
+          options
+
+        {%Nx.Tensor{shape: {grid_height, grid_width}} = grid, {width, height}}
+        when is_integer(width) and is_integer(height) and grid_height > 0 and grid_width > 0 ->
+          weights = grid |> Nx.as_type({:f, 32}) |> Nx.to_flat_list()
+          %{options | prior_grid: {grid_height, grid_width, weights}}
+
+        _ ->
+          raise ArgumentError,
+                "prior_grid must be a {grid_height, grid_width} tensor " <>
+                  "and requires image_size: {width, height}"
+      end
+
+    options =
+      case options.area_weights do
+        nil ->
+          options
+
+        steps when is_list(steps) ->
+          steps =
+            Enum.map(steps, fn
+              {max_area, weight} when is_number(max_area) and is_number(weight) and weight >= 0 ->
+                {max_area / 1, weight / 1}
+
+              step ->
+                raise ArgumentError,
+                      "area_weights must be a list of {max_area, weight}, got: #{inspect(step)}"
+            end)
+
+          %{options | area_weights: steps}
+      end
+
+    options =
+      case options.calibration do
+        nil ->
+          options
+
+        [_ | _] = points ->
+          points =
+            Enum.map(points, fn
+              {input, output} when is_number(input) and is_number(output) ->
+                {input / 1, output / 1}
+
+              point ->
+                raise ArgumentError,
+                      "calibration must be a list of {input, output}, got: #{inspect(point)}"
+            end)
+
+          inputs = Enum.map(points, &elem(&1, 0))
+
+          if inputs != Enum.uniq(Enum.sort(inputs)) do
+            raise ArgumentError, "calibration inputs must be strictly increasing"
+          end
+
+          %{options | calibration: points}
+      end
+
+    if options.oversize_policy != :keep and options.image_size == nil do
+      raise ArgumentError,
+            "oversize_policy: #{inspect(options.oversize_policy)} requires image_size: {width, height}"
+    end
+
+    if options.format == :yolo_label and (options.image_size == nil or options.obb) do
+      raise ArgumentError, "format: :yolo_label requires image_size: {width, height} and obb: false"
+    end
+
+    if options.clamp do
+      case options.image_size do
+        {width, height} when is_integer(width) and is_integer(height) -> :ok
+        _ -> raise ArgumentError, "clamp: true requires image_size: {width, height}"
+      end
+
+      if options.obb do
+        raise ArgumentError, "clamp: true is not supported with obb: true"
+      end
+    end
+
+    if options.sort_order == :asc and options.method != :hard do
+      raise ArgumentError, "sort_order: :asc requires method: :hard"
+    end
+
+    if options.geometry == :union and (options.method != :hard or options.obb) do
+      raise ArgumentError, "geometry: :union requires method: :hard and obb: false"
+    end
+
+    unless options.valid_columns == nil or
+             (is_integer(options.valid_columns) and options.valid_columns > 0) do
+      raise ArgumentError,
+            "valid_columns must be a positive integer, got: #{inspect(options.valid_columns)}"
+    end
+
+    unless is_integer(options.min_support) and options.min_support >= 0 do
+      raise ArgumentError,
+            "min_support must be a non-negative integer, got: #{inspect(options.min_support)}"
+    end
+
+    if options.min_support > 0 and options.suppressed do
+      raise ArgumentError, "min_support is not supported with suppressed: true"
+    end
+
+    if options.min_center_distance != nil and options.suppressed do
+      raise ArgumentError, "min_center_distance is not supported with suppressed: true"
+    end
+
+    if options.force_keep_threshold != nil and options.suppressed do
+      raise ArgumentError, "force_keep_threshold is not supported with suppressed: true"
+    end
+
+    if options.suppressed and
+         (not options.metadata or options.group_by_class or options.method not in [:hard, :soft]) do
+      raise ArgumentError,
+            "suppressed: true requires metadata: true, group_by_class: false and method :hard or :soft"
+    end
+
+    if options.fingerprint and not options.metadata do
+      raise ArgumentError, "fingerprint: true requires metadata: true"
+    end
+
+    if options.duplicate_rows and not options.metadata do
+      raise ArgumentError, "duplicate_rows: true requires metadata: true"
+    end
+
+    if options.class_centroids and not options.metadata do
+      raise ArgumentError, "class_centroids: true requires metadata: true"
+    end
+
+    if options.corners and options.obb do
+      raise ArgumentError, "corners: true is not supported with obb: true"
+    end
+
+    if options.coverage and (not options.metadata or options.obb) do
+      raise ArgumentError, "coverage: true requires metadata: true and obb: false"
+    end
+
+    if options.relabel and options.keep_classes == nil do
+      raise ArgumentError, "relabel: true requires keep_classes"
+    end
+
+    options =
+      case options.objectness_classes do
+        nil ->
+          options
+
+        ranges when is_list(ranges) and options.objectness ->
+          %{options | objectness_classes: Enum.map(ranges, &objectness_range/1)}
+
+        ranges ->
+          raise ArgumentError,
+                "objectness_classes requires objectness: true and a list of ranges, " <>
+                  "got: #{inspect(ranges)}"
+      end
+
+    case {options.score_column, options.class_column} do
+      {nil, nil} ->
+        :ok
+
+      {score_column, class_column}
+      when is_integer(score_column) and score_column >= 4 and is_integer(class_column) and
+             class_column >= 4 and score_column != class_column ->
+        if options.argmaxed or options.objectness or options.multi_label do
+          raise ArgumentError,
+                "score_column and class_column are not supported with argmaxed, objectness " <>
+                  "and multi_label"
+        end
+
+      columns ->
+        raise ArgumentError,
+              "score_column and class_column must be two different feature indexes " <>
+                "after the box, got: #{inspect(columns)}"
+    end
+
+    if options.multi_label and options.argmaxed do
+      raise ArgumentError, "multi_label: true is not supported with argmaxed: true"
+    end
+
+    if options.multi_label and options.score_type == :softmax do
+      raise ArgumentError, "multi_label: true requires score_type: :sigmoid"
+    end
+
+    if options.format != :map do
+      for key <- @map_only_options, options[key] do
+        raise ArgumentError, "option #{inspect(key)} requires format: :map"
+      end
+
+      if options.top_k_classes > 1 do
+        raise ArgumentError, "top_k_classes above 1 requires format: :map"
+      end
+    end
+
+    options
+  end
+
+  # The `transpose` inferred by `auto_transpose`, the smaller dimension being the features.
+  # An explicit `transpose` wins.
+  defp orient(options, rows, columns) do
+    cond do
+      not Keyword.get(options, :auto_transpose, false) or Keyword.has_key?(options, :transpose) ->
+        options
+
+      rows == columns ->
+        options
+
+      true ->
+        Keyword.put(options, :transpose, rows < columns)
+    end
+  end
+
+  defp objectness_range(first..last//1) when first >= 0 and first <= last, do: {first, last}
+  # already converted, like the options of a handle
+  defp objectness_range({first, last}) when first >= 0 and first <= last, do: {first, last}
+
+  defp objectness_range(range) do
+    raise ArgumentError,
+          "objectness_classes must be increasing ranges of class indexes, got: #{inspect(range)}"
+  end
+
+  @doc """
+  Creates a handle to run NMS over tensors of the same shape, like the frames of a video.
+
+  The handle owns the buffers of the parsed detections and of the candidates. They are cleared
+  but not freed between the runs of `handle_run/2`, so the repeated runs don't allocate them again.
+
+  ## Parameters
+
+    - `shape`: The `{rows, columns}` (or `{1, rows, columns}`) shape of the tensors.
+    - `options`: The options accepted by `run/2`, used by every run, plus:
+      - `:temporal_iou` (float, default: nil) — Tracks the detections across the runs, to keep a stable
+        id for the same object between frames. Each detection has a `:track_id` key, the id of the
+        previous frame detection of the same class (of any class with `agnostic: true`) it overlaps the
+        most with an IoU of at least `:temporal_iou`, or a new id. A previous detection gives its id
+        to one detection only. Requires `format: :map`.
+
+  ## Returns
+
+    - A reference to the handle.
+  """
+  @spec handle_new(shape :: tuple(), options :: keyword()) :: reference()
+  def handle_new(shape, options \\ [])
+  def handle_new({1, rows, columns}, options), do: handle_new({rows, columns}, options)
+
+  def handle_new({rows, columns}, options) when is_integer(rows) and is_integer(columns) do
+    handle_new_nif(rows, columns, nif_options(orient(options, rows, columns)))
+  end
+
+  @doc false
+  def handle_new_nif(_rows, _columns, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Runs NMS on a tensor (or its binary) with the shape and the options of the handle.
+
+  Same result of `run/2`. The runs on the same handle are serialized, use a handle for each process
+  running in parallel.
+
+  `overrides` are suppression options of `run/2` taking precedence over the handle ones for this run
+  only, like a different `:overlap_metric` or `:iou_threshold` for the keyframes. The handle isn't changed,
+  the next runs use its options again. The options that can be overridden are
+  #{Enum.map_join(@overridable_options, ", ", &"`#{inspect(&1)}`")}.
+  """
+  @spec handle_run(
+          handle :: reference(),
+          tensor :: Nx.Tensor.t() | binary(),
+          overrides :: keyword()
+        ) :: [[float()]] | [map()] | map() | {[[float()]] | [map()] | map(), map()}
+  def handle_run(handle, tensor, overrides \\ [])
+
+  def handle_run(handle, %Nx.Tensor{} = tensor, overrides),
+    do: handle_run(handle, Nx.to_binary(tensor), overrides)
+
+  def handle_run(handle, binary, []) when is_binary(binary), do: handle_run_nif(handle, binary, nil)
+
+  def handle_run(handle, binary, overrides) when is_binary(binary) and is_list(overrides) do
+    case Keyword.keys(overrides) -- @overridable_options do
+      [] -> :ok
+      keys -> raise ArgumentError, "options #{inspect(keys)} can't be overridden for a single run"
+    end
+
+    # validated with the handle options, for the combinations `run/2` rejects
+    options =
+      handle
+      |> handle_options_nif()
+      |> Map.merge(Map.new(overrides))
+      |> Map.to_list()
+      |> nif_options()
+
+    overrides =
+      Map.new(@overridable_options, &{&1, if(Keyword.has_key?(overrides, &1), do: options[&1])})
+    handle_run_nif(handle, binary, overrides)
+  end
+
+  @doc false
+  def handle_run_nif(_handle, _binary, _overrides), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc false
+  def handle_options_nif(_handle), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Offers a detection to the incremental hard NMS of the handle and returns the decision right away,
+  for the pipelines producing the candidates one at a time, already sorted by `prob` descending: the
+  first detections can be drawn before the last ones are produced.
+
+  The detection is `:suppressed` when a detection kept so far (of its class, of any class with
+  `agnostic: true`) overlaps it above the `:iou_threshold` of the handle, otherwise it's `:kept`
+  and suppresses the next ones. The decisions are final, a detection offered out of order doesn't
+  change them. `:prob_threshold` isn't applied, the caller decides what to offer.
+
+  The kept detections are the ones offered since the last `offer_reset/1`, independent of
+  `handle_run/3`.
+
+  ## Parameters
+
+    - `handle`: A handle created with `handle_new/2`.
+    - `detection`: `[cx, cy, w, h, prob, class_idx]` (plus the angle with `obb: true`), as returned by `run/2`.
+  """
+  @spec offer(handle :: reference(), detection :: [number()]) :: :kept | :suppressed
+  def offer(handle, detection) when is_list(detection),
+    do: handle_offer_nif(handle, Enum.map(detection, &(&1 / 1)))
+
+  @doc false
+  def handle_offer_nif(_handle, _detection), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Forgets the detections kept by `offer/2`, to start the incremental NMS of a new frame.
+  """
+  @spec offer_reset(handle :: reference()) :: :ok
+  def offer_reset(handle), do: handle_offer_reset_nif(handle)
+
+  @doc false
+  def handle_offer_reset_nif(_handle), do: :erlang.nif_error(:nif_not_loaded)
+
+  # Heap allocations of the NIF, only with the `alloc_count` feature.
+  @doc false
+  def allocations_nif, do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Creates a candidate stream, to run NMS over a model output fed in chunks.
+
+  Each chunk pushed with `stream_push/2` is parsed and only the candidates above `:prob_threshold`
+  are buffered. `stream_finalize/2` then runs NMS over everything collected so far.
+
+  ## Parameters
+
+    - `columns`: Number of values per detection (4 bounding box parameters + class probabilities), the same for every chunk.
+    - `options`: Keyword list of options:
+      - `:max_candidates` (integer, default: 100_000) — Maximum number of buffered candidates. A chunk that would exceed it is rejected.
+      - The parsing options accepted by `run/2`, applied to every chunk, like `:prob_threshold` and `:transpose` (whether the chunks have shape `{columns, rows}` instead of `{rows, columns}`).
+
+  ## Returns
+
+    - A reference to the stream.
+  """
+  @spec stream_new(columns :: pos_integer(), options :: keyword()) :: reference()
+  def stream_new(columns, options \\ []) when is_integer(columns) and columns > 4 do
+    {max_candidates, options} = Keyword.pop(options, :max_candidates, 100_000)
+    stream_new_nif(columns, max_candidates, nif_options(options))
+  end
+
+  @doc false
+  def stream_new_nif(_columns, _max_candidates, _options),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Parses a chunk of detections and adds its candidates to the stream buffer.
+
+  The chunk is an `Nx.Tensor` or a binary of f32 values, with shape `{rows, columns}` (or `{columns, rows}` when the stream transposes).
+  The number of rows can change from chunk to chunk.
+
+  ## Returns
+
+    - `{:ok, count}` with the number of buffered candidates.
+    - `{:error, :max_candidates_exceeded}` if the chunk candidates don't fit in the buffer. The buffer is left untouched.
+    - `{:error, :invalid_chunk_size}` if the binary size is not a multiple of the row size.
+  """
+  @spec stream_push(stream :: reference(), chunk :: Nx.Tensor.t() | binary()) ::
+          {:ok, non_neg_integer()} | {:error, atom()}
+  def stream_push(stream, %Nx.Tensor{} = chunk), do: stream_push(stream, Nx.to_binary(chunk))
+
+  def stream_push(stream, chunk) when is_binary(chunk), do: stream_push_nif(stream, chunk)
+
+  @doc false
+  def stream_push_nif(_stream, _chunk), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Runs NMS over all the candidates buffered in the stream.
+
+  The buffer is not emptied, use `stream_reset/1` to start over.
+
+  ## Parameters
+
+    - `stream`: The stream created with `stream_new/2`.
+    - `options`: The NMS and output options accepted by `run/2`, like `:iou_threshold`. The parsing options are the ones given to `stream_new/2` and are ignored here.
+
+  ## Returns
+
+    - A list of detections, like `run/2`.
+  """
+  @spec stream_finalize(stream :: reference(), options :: keyword()) :: [[float()]] | [map()]
+  def stream_finalize(stream, options \\ []) when is_list(options) do
+    stream_finalize_nif(stream, nif_options(options))
+  end
+
+  @doc false
+  def stream_finalize_nif(_stream, _options), do: :erlang.nif_error(:nif_not_loaded)
+
+  @doc """
+  Empties the stream buffer and releases its memory.
+  """
+  @spec stream_reset(stream :: reference()) :: :ok
+  def stream_reset(stream), do: stream_reset_nif(stream)
+
+  @doc false
+  def stream_reset_nif(_stream), do: :erlang.nif_error(:nif_not_loaded)
 end
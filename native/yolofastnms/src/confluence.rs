@@ -0,0 +1,70 @@
+use crate::{BBox, NmsOptions};
+
+// Confluence (Shepley et al.): the boxes are clustered by the proximity of their corners
+// instead of their IoU. Over and over, the box with the lowest accumulated proximity to its
+// neighbours (weighted by its `prob`) represents its cluster and the neighbours closer than
+// `proximity_threshold` are removed.
+pub(crate) fn confluence(bboxes: Vec<BBox>, options: &NmsOptions) -> Vec<BBox> {
+    let n = bboxes.len();
+    let mut proximities = vec![vec![0.0_f32; n]; n];
+    for i in 0..n {
+        for j in 0..i {
+            let p = proximity(&bboxes[i], &bboxes[j]);
+            proximities[i][j] = p;
+            proximities[j][i] = p;
+        }
+    }
+
+    let is_neighbour = |i: usize, j: usize| i != j && proximities[i][j] < options.proximity_threshold;
+
+    let mut remaining: Vec<usize> = (0..n).collect();
+    let mut kept_indexes: Vec<usize> = Vec::new();
+
+    while !remaining.is_empty() {
+        let weighted_proximity = |i: usize| {
+            let accumulated: f32 = remaining
+                .iter()
+                .filter(|&&j| is_neighbour(i, j))
+                .map(|&j| proximities[i][j])
+                .sum();
+            accumulated / bboxes[i].prob.max(f32::MIN_POSITIVE)
+        };
+
+        // computed once for each remaining box, each is a scan of the remaining boxes
+        let weighted_proximities: Vec<f32> = remaining.iter().map(|&i| weighted_proximity(i)).collect();
+        let best = remaining
+            .iter()
+            .copied()
+            .zip(weighted_proximities)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i)
+            .unwrap();
+
+        remaining.retain(|&j| j != best && !is_neighbour(best, j));
+        kept_indexes.push(best);
+    }
+
+    let mut bboxes: Vec<Option<BBox>> = bboxes.into_iter().map(Some).collect();
+    kept_indexes.into_iter().filter_map(|i| bboxes[i].take()).collect()
+}
+
+// Manhattan distance between the corners of the two boxes, normalized by the extent of the
+// pair on each axis: 0 for the same box, up to 4 for far away boxes.
+fn proximity(a: &BBox, b: &BBox) -> f32 {
+    let (a_x1, a_y1, a_x2, a_y2) = corners(a);
+    let (b_x1, b_y1, b_x2, b_y2) = corners(b);
+
+    let x_extent = a_x2.max(b_x2) - a_x1.min(b_x1);
+    let y_extent = a_y2.max(b_y2) - a_y1.min(b_y1);
+
+    let normalized = |distance: f32, extent: f32| if extent > 0.0 { distance / extent } else { 0.0 };
+
+    normalized((a_x1 - b_x1).abs() + (a_x2 - b_x2).abs(), x_extent)
+        + normalized((a_y1 - b_y1).abs() + (a_y2 - b_y2).abs(), y_extent)
+}
+
+fn corners(bbox: &BBox) -> (f32, f32, f32, f32) {
+    let (cx, cy) = (bbox.cx as f32, bbox.cy as f32);
+    let (hw, hh) = (bbox.w as f32 / 2.0, bbox.h as f32 / 2.0);
+    (cx - hw, cy - hh, cx + hw, cy + hh)
+}
@@ -0,0 +1,217 @@
+use rustler::{Atom, Binary, Env, Error, NifMap, NifResult, ResourceArc, Term};
+
+use std::borrow::Cow;
+use std::sync::Mutex;
+
+use crate::{
+    BBox, NmsMethod, NmsOptions, OverlapMetric, Precision, Trace, bbox_from_row, boxes_approx_equal,
+    check_box_encoding, count_duplicate_rows, detect, encode_bboxes, overlap, payload, read_bboxes_columnwise,
+    read_detections_into, select, class_from_f32, hard_suppresses,
+};
+
+mod atoms {
+    rustler::atoms! {
+        ok,
+        kept,
+        suppressed,
+    }
+}
+
+// NMS over tensors of the same `{rows, columns}` shape, like the frames of a video.
+// The parsed detections and the candidates are kept in scratch buffers owned by the
+// handle, cleared but not freed between runs, so after the first run only the few
+// kept boxes are allocated.
+// With `temporal_iou` the handle also remembers the kept boxes of the previous frame, to carry
+// their track ids forward.
+// The handle also runs the incremental NMS of `handle_offer`, over the boxes kept so far.
+struct NmsHandle {
+    rows: usize,
+    columns: usize,
+    options: NmsOptions,
+    scratch: Mutex<Scratch>,
+}
+
+#[derive(Default)]
+struct Scratch {
+    // only used by the row-major tensors, the `transpose` ones are read column-wise
+    matrix: Vec<Vec<f32>>,
+    bboxes: Vec<BBox>,
+    // the kept boxes of the previous frame and the id of the next new track
+    tracks: Vec<BBox>,
+    next_track_id: usize,
+    // the boxes kept by `handle_offer` since the last `handle_offer_reset`
+    offered: Vec<BBox>,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for NmsHandle {}
+
+// Suppression options overriding the handle ones for a single run, `nil` for the options
+// not overridden.
+#[derive(NifMap)]
+struct NmsOverrides {
+    prob_threshold: Option<f32>,
+    iou_threshold: Option<f32>,
+    method: Option<NmsMethod>,
+    overlap_metric: Option<OverlapMetric>,
+    sigma: Option<f32>,
+    agnostic: Option<bool>,
+    threshold_inclusive: Option<bool>,
+    iou_eps: Option<f32>,
+    precision: Option<Precision>,
+}
+
+impl NmsOverrides {
+    fn apply(self, options: &NmsOptions) -> NmsOptions {
+        NmsOptions {
+            prob_threshold: self.prob_threshold.unwrap_or(options.prob_threshold),
+            iou_threshold: self.iou_threshold.unwrap_or(options.iou_threshold),
+            method: self.method.unwrap_or(options.method),
+            overlap_metric: self.overlap_metric.unwrap_or(options.overlap_metric),
+            sigma: self.sigma.unwrap_or(options.sigma),
+            agnostic: self.agnostic.unwrap_or(options.agnostic),
+            threshold_inclusive: self.threshold_inclusive.unwrap_or(options.threshold_inclusive),
+            iou_eps: self.iou_eps.unwrap_or(options.iou_eps),
+            precision: self.precision.unwrap_or(options.precision),
+            ..options.clone()
+        }
+    }
+}
+
+#[rustler::nif(name = "handle_new_nif")]
+fn handle_new(rows: usize, columns: usize, options: NmsOptions) -> ResourceArc<NmsHandle> {
+    ResourceArc::new(NmsHandle {
+        rows,
+        columns,
+        options,
+        scratch: Mutex::new(Scratch::default()),
+    })
+}
+
+// The options of the handle, to validate the overrides of a run with them.
+#[rustler::nif(name = "handle_options_nif")]
+fn handle_options(handle: ResourceArc<NmsHandle>) -> NmsOptions {
+    handle.options.clone()
+}
+
+// Same as `run_nif` with the shape and the options of the handle, with the `overrides` of this
+// run only. Concurrent runs on the same handle are serialized by the scratch lock.
+#[rustler::nif(name = "handle_run_nif")]
+fn handle_run<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<NmsHandle>,
+    binary: Binary,
+    overrides: Option<NmsOverrides>
+) -> NifResult<Term<'a>> {
+    let options = match overrides {
+        Some(overrides) => Cow::Owned(overrides.apply(&handle.options)),
+        None => Cow::Borrowed(&handle.options),
+    };
+    let options = options.as_ref();
+    let mut scratch = handle.scratch.lock().unwrap();
+    let Scratch { matrix, bboxes, tracks, next_track_id, .. } = &mut *scratch;
+    let binary = payload(binary.as_slice(), handle.rows, handle.columns, options);
+    let mut trace = Trace::default();
+    if options.duplicate_rows {
+        trace.duplicate_rows = count_duplicate_rows(binary, handle.rows, handle.columns, options)?;
+    }
+
+    let mut final_bboxes = if options.transpose && !options.sanity_check {
+        trace.dropped = read_bboxes_columnwise(binary, handle.rows, handle.columns, options, bboxes)?;
+        select(options, bboxes, &mut trace)
+    } else {
+        read_detections_into(binary, handle.rows, handle.columns, options, matrix)?;
+        if options.sanity_check {
+            check_box_encoding(matrix)?;
+        }
+        detect(matrix, options, bboxes, &mut trace)
+    };
+
+    if let Some(temporal_iou) = options.temporal_iou {
+        track(&mut final_bboxes, tracks, next_track_id, temporal_iou, options);
+    }
+
+    encode_bboxes(env, final_bboxes, trace, options)
+}
+
+// Incremental hard NMS of the detections offered one at a time, already sorted by `prob` descending:
+// the detection `[cx, cy, w, h, prob, class_idx]` (plus the angle with `obb`) is suppressed by a
+// kept box of its class (of any class with `agnostic`) overlapping it above `iou_threshold`, like
+// in the greedy loop of `nms` (any kept box with a zero threshold), otherwise it's kept.
+// The decision is final.
+#[rustler::nif(name = "handle_offer_nif")]
+fn handle_offer(handle: ResourceArc<NmsHandle>, detection: Vec<f32>) -> NifResult<Atom> {
+    let options = &handle.options;
+    let detection_size = if options.obb { 7 } else { 6 };
+    if detection.len() != detection_size || !detection[4].is_finite() {
+        return Err(Error::BadArg);
+    }
+    let mut bbox = bbox_from_row(&detection[..4], &[]).ok_or(Error::BadArg)?;
+    bbox.prob = detection[4];
+    bbox.class = class_from_f32(detection[5]).ok_or(Error::BadArg)?;
+    if options.obb {
+        bbox.angle = detection[6];
+    }
+
+    let mut scratch = handle.scratch.lock().unwrap();
+    let suppressed = scratch
+        .offered
+        .iter()
+        .any(|kept| (options.agnostic || kept.class == bbox.class) && hard_suppresses(&bbox, kept, options));
+    if suppressed {
+        return Ok(atoms::suppressed());
+    }
+    scratch.offered.push(bbox);
+    Ok(atoms::kept())
+}
+
+#[rustler::nif(name = "handle_offer_reset_nif")]
+fn handle_offer_reset(handle: ResourceArc<NmsHandle>) -> Atom {
+    handle.scratch.lock().unwrap().offered.clear();
+    atoms::ok()
+}
+
+// Tracker-lite: each kept box takes the id of the previous frame box of the same class
+// (of any class with `agnostic`) it overlaps the most, at least by `temporal_iou` or equal
+// within the `match_tolerance`. A previous box is matched at most once, by the first box in
+// the NMS order. The unmatched boxes start new tracks. The kept boxes then replace the
+// previous ones.
+fn track(
+    bboxes: &mut [BBox],
+    tracks: &mut Vec<BBox>,
+    next_track_id: &mut usize,
+    temporal_iou: f32,
+    options: &NmsOptions
+) {
+    let mut matched = vec![false; tracks.len()];
+    for bbox in bboxes.iter_mut() {
+        let best = tracks
+            .iter()
+            .enumerate()
+            .filter(|&(i, previous)| !matched[i] && (options.agnostic || previous.class == bbox.class))
+            .map(|(i, previous)| (i, previous, overlap(bbox, previous, options)))
+            .filter(|&(_, previous, iou)| {
+                let same_box = |tolerance: f32| boxes_approx_equal(bbox, previous, tolerance);
+                iou >= temporal_iou || options.match_tolerance.is_some_and(same_box)
+            })
+            .map(|(i, _, iou)| (i, iou))
+            .fold(None, |best: Option<(usize, f32)>, (i, iou)| match best {
+                Some((_, best_iou)) if best_iou >= iou => best,
+                _ => Some((i, iou)),
+            });
+
+        bbox.track_id = match best {
+            Some((i, _)) => {
+                matched[i] = true;
+                tracks[i].track_id
+            }
+            None => {
+                *next_track_id += 1;
+                Some(*next_track_id - 1)
+            }
+        };
+    }
+
+    tracks.clear();
+    tracks.extend(bboxes.iter().cloned());
+}
@@ -1,174 +1,2044 @@
-use rustler::{Binary, Env, Term, NifResult, Encoder};
+use rustler::{Atom, Binary, Encoder, Env, Error, Term, NifResult, NifMap, NifTaggedEnum, NifUnitEnum};
 
-use std::collections::HashSet;
-use std::convert::TryInto;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "alloc_count")]
+mod alloc_count;
+mod confluence;
+mod handle;
+mod output;
+mod rotated;
+mod soa;
+mod stream;
+
+use confluence::confluence;
+use output::encode_bboxes;
+use rotated::rotated_iou;
+use soa::{FixedIouColumns, IouColumns};
+
+mod atoms {
+    rustler::atoms! {
+        f,
+    }
+}
 
 #[derive(Debug, Clone)]
-struct BBox {
-    prob: f32,
-    class: u16,
-    cx: i32,
-    cy: i32,
-    w: i32,
-    h: i32,
+pub struct BBox {
+    pub prob: f32,
+    pub class: u16,
+    pub cx: i32,
+    pub cy: i32,
+    pub w: i32,
+    pub h: i32,
+    // rotation in radians, always 0.0 for non-oriented boxes
+    pub angle: f32,
+    // index of the detection in the tensor
+    row: usize,
+    // set when `clamp` moved the box edges inside the image
+    clamped: bool,
+    // `row[4..]`, only filled when the `class_probs` or `class_scores` option is set
+    class_probs: Vec<f32>,
+    // the `top_k_classes` most probable `(class, prob)` of the row, only filled for k > 1
+    top_classes: Vec<(u16, f32)>,
+    // id carried across the frames of a handle with `temporal_iou`
+    track_id: Option<usize>,
+    // group id from the `group_column`, like the tile of the detection, 0 without it
+    group: u16,
 }
 
-#[rustler::nif]
-fn run_with_binary<'a>(
-    env: Env<'a>, 
-    binary: Binary, 
+// How the class probabilities were produced: independent per-class sigmoids
+// (YOLO, several classes can be high) or a softmax (they sum to 1).
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum ScoreType {
+    Sigmoid,
+    Softmax,
+}
+
+// Hard NMS drops the overlapping boxes, Soft-NMS and Matrix NMS decay their `prob`
+// by a gaussian of the IoU and drop them once below `prob_threshold`.
+// Point NMS drops the boxes by the distance of their centers, for the center-based models.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum NmsMethod {
+    Hard,
+    Soft,
+    Matrix,
+    Confluence,
+    Point,
+}
+
+// How the objectness and the class probability are fused into `prob`.
+#[derive(NifTaggedEnum, Clone, Copy, PartialEq)]
+enum ScoreCombine {
+    // `objectness * class_prob`, like YOLOv5
+    Product,
+    // `sqrt(objectness * class_prob)`
+    GeometricMean,
+    // `obj_weight * objectness + (1 - obj_weight) * class_prob`
+    Weighted(f32),
+}
+
+impl ScoreCombine {
+    fn combine(self, objectness: f32, class_prob: f32) -> f32 {
+        match self {
+            ScoreCombine::Product => objectness * class_prob,
+            ScoreCombine::GeometricMean => (objectness * class_prob).sqrt(),
+            ScoreCombine::Weighted(obj_weight) => obj_weight * objectness + (1.0 - obj_weight) * class_prob,
+        }
+    }
+}
+
+// Arithmetic of the axis-aligned IoU, f64 for the large images where the f32 areas lose precision,
+// or the integer fixed-point approximation for the targets without a fast float division.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum Precision {
+    F32,
+    F64,
+    Fixed,
+}
+
+// Which box comes first when two boxes have the same `prob`: the first in the
+// tensor (the sort is stable), or the one with the larger or the smaller area.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum TiePrefer {
+    First,
+    LargerArea,
+    SmallerArea,
+}
+
+impl TiePrefer {
+    fn compare(self, a: &BBox, b: &BBox) -> Ordering {
+        let area = |bbox: &BBox| bbox.w as i64 * bbox.h as i64;
+        match self {
+            TiePrefer::First => Ordering::Equal,
+            TiePrefer::LargerArea => area(b).cmp(&area(a)),
+            TiePrefer::SmallerArea => area(a).cmp(&area(b)),
+        }
+    }
+}
+
+// Denominator of the overlap: the union (IoU), or the smaller (IoMin) or the larger (IoMax)
+// of the two areas. IoMin is 1 for a box nested in a larger one.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum OverlapMetric {
+    Iou,
+    Iomin,
+    Iomax,
+}
+
+// IoU of two zero-area boxes, where the union is 0: 0.0, or 1.0 for the coincident boxes.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum ZeroUnionIou {
+    Zero,
+    One,
+}
+
+// Geometry of the boxes kept by hard NMS: their own, or the union of the box and of
+// the boxes it suppressed, to cover the large objects detected in parts.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum Geometry {
+    Kept,
+    Union,
+}
+
+// Byte order of the floats of `format: :binary`.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum Endianness {
+    Native,
+    Little,
+    Big,
+}
+
+// What to do with the malformed rows: skip them and count them, or raise.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum ParseMode {
+    Lenient,
+    Strict,
+}
+
+// What to do with the boxes wider or taller than the `image_size` image.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum OversizePolicy {
+    Keep,
+    Clamp,
+    Drop,
+}
+
+// Order of the returned detections: the NMS one, or top-to-bottom then left-to-right.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum SortOrder {
+    Nms,
+    Raster,
+}
+
+// Order of the candidates in the greedy loop of the hard NMS: by `prob` descending, or the
+// experimental ascending order keeping the less probable representatives.
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum ProbOrder {
+    Desc,
+    Asc,
+}
+
+#[derive(NifUnitEnum, Clone, Copy, PartialEq)]
+enum OutputFormat {
+    List,
+    Map,
+    Boxes,
+    Binary,
+    Flat,
+    YoloLabel,
+}
+
+// Options decoded from the map built by `YoloFastNMS.run/2`, every key is always present.
+#[derive(NifMap, Clone)]
+struct NmsOptions {
     prob_threshold: f32,
     iou_threshold: f32,
+    transpose: bool,
+    dedup: bool,
+    dedup_tolerance: f32,
+    agnostic: bool,
+    class_priorities: Option<HashMap<u16, f32>>,
+    format: OutputFormat,
+    class_probs: bool,
+    min_results: usize,
+    obb: bool,
+    group_by_class: bool,
+    score_type: ScoreType,
+    margin_threshold: f32,
+    multi_label: bool,
+    method: NmsMethod,
+    sigma: f32,
+    proximity_threshold: f32,
+    row_offset: usize,
+    row_count: Option<usize>,
+    row_index: bool,
+    scale: f32,
+    pixel_inclusive: bool,
+    input_sorted: bool,
+    image_size: Option<(i32, i32)>,
+    clamp: bool,
+    clamped: bool,
+    objectness: bool,
+    score_combine: ScoreCombine,
+    precision: Precision,
+    metadata: bool,
+    tie_prefer: TiePrefer,
+    sanity_check: bool,
+    display: bool,
+    display_threshold: Option<f32>,
+    overlap_metric: OverlapMetric,
+    max_candidates_per_class: Option<usize>,
+    class_names: Option<Vec<String>>,
+    geometry: Geometry,
+    class_sigmas: Option<HashMap<u16, f32>>,
+    suppressed: bool,
+    radius: f32,
+    letterbox: Option<(f32, f32, f32)>,
+    offset_x: f32,
+    offset_y: f32,
+    fingerprint: bool,
+    argmaxed: bool,
+    score_column: Option<usize>,
+    class_column: Option<usize>,
+    threshold_inclusive: bool,
+    zero_union_iou: ZeroUnionIou,
+    iou_eps: f32,
+    top_n: Option<usize>,
+    auto_transpose: bool,
+    class_tie_epsilon: Option<f32>,
+    threads: usize,
+    temporal_iou: Option<f32>,
+    coverage: bool,
+    candidate_budget: Option<usize>,
+    corners: bool,
+    group_column: bool,
+    parse_mode: ParseMode,
+    area_weights: Option<Vec<(f32, f32)>>,
+    min_size: f32,
+    class_min_sizes: Option<HashMap<u16, f32>>,
+    duplicate_rows: bool,
+    class_centroids: bool,
+    sort: SortOrder,
+    sort_order: ProbOrder,
+    calibration: Option<Vec<(f32, f32)>>,
+    grid_max: Option<f32>,
+    class_scores: Option<Vec<u16>>,
+    palette_size: Option<usize>,
+    oversize_policy: OversizePolicy,
+    byte_offset: usize,
+    top_k_classes: usize,
+    min_support: usize,
+    support_iou: Option<f32>,
+    match_tolerance: Option<f32>,
+    valid_columns: Option<usize>,
+    min_center_distance: Option<f32>,
+    sorted_classes: bool,
+    force_keep_threshold: Option<f32>,
+    band_low: Option<f32>,
+    band_high: Option<f32>,
+    endianness: Endianness,
+    objectness_classes: Option<Vec<(u16, u16)>>,
+    never_suppress: Option<Vec<u16>>,
+    // `{rows, columns, weights}` row-major grid over the `image_size` image
+    prior_grid: Option<(usize, usize, Vec<f32>)>,
+    keep_classes: Option<Vec<u16>>,
+    relabel: bool,
+}
+
+impl NmsOptions {
+    // The coordinate tolerance of the duplicates of `dedup`: the shared `match_tolerance`,
+    // or the `dedup_tolerance`.
+    fn dedup_tolerance(&self) -> f32 {
+        self.match_tolerance.unwrap_or(self.dedup_tolerance)
+    }
+
+    // Whether the `prob` of the candidates is rescaled after the parsing, possibly above 1.0.
+    fn rescales_prob(&self) -> bool {
+        self.area_weights.is_some() || self.prior_grid.is_some() || self.calibration.is_some()
+    }
+
+    // Whether a run checks or reports the rows themselves besides the candidates: the box encoding,
+    // the repeated rows and the malformed rows (raised when strict, counted in the metadata).
+    fn inspects_rows(&self) -> bool {
+        self.sanity_check || self.duplicate_rows || self.parse_mode == ParseMode::Strict || self.metadata
+    }
+}
+
+// A box removed by the NMS with the kept box that suppressed it, reported with `suppressed`.
+struct Suppressed {
+    bbox: BBox,
+    // index of the suppressing box in the kept boxes
+    by: usize,
+    iou: f32,
+}
+
+// What a run reports in the metadata besides the detections.
+#[derive(Default)]
+struct Trace {
+    // the boxes removed by the NMS, with `suppressed`
+    suppressed: Vec<Suppressed>,
+    // whether the candidates were over the `candidate_budget`
+    truncated: bool,
+    // the malformed rows skipped by the parsing
+    dropped: usize,
+    // the rows identical to a previous one, with `duplicate_rows`
+    duplicate_rows: usize,
+}
+
+#[rustler::nif]
+fn run_nif<'a>(
+    env: Env<'a>,
+    binary: Binary,
     rows: usize,
     columns: usize,
-    transpose: bool
+    options: NmsOptions
+) -> NifResult<Term<'a>> {
+    let binary = payload(binary.as_slice(), rows, columns, &options);
+
+    // no probability is above 1.0, no candidate can pass, unless the weights or the calibration rescale it:
+    // the rows are still parsed for their checks, only the NMS is skipped
+    let nothing_passes = options.prob_threshold > 1.0 && options.min_results == 0 && !options.rescales_prob();
+    if nothing_passes && !options.inspects_rows() {
+        assert_binary_size(binary, rows, columns);
+        return encode_bboxes(env, Vec::new(), Trace::default(), &options);
+    }
+    let select_candidates = |bboxes: &mut Vec<BBox>, trace: &mut Trace| {
+        if nothing_passes { Vec::new() } else { select(&options, bboxes, trace) }
+    };
+
+    let mut trace = Trace::default();
+    if options.duplicate_rows {
+        trace.duplicate_rows = count_duplicate_rows(binary, rows, columns, &options)?;
+    }
+    // the `{features, detections}` YOLOv8 layout is read column-wise, without a transposed matrix
+    if options.transpose && !options.sanity_check {
+        let mut bboxes = Vec::new();
+        trace.dropped = read_bboxes_columnwise(binary, rows, columns, &options, &mut bboxes)?;
+        let final_bboxes = select_candidates(&mut bboxes, &mut trace);
+        return encode_bboxes(env, final_bboxes, trace, &options);
+    }
+
+    // load the detections matrix `Vec<Vec<f32>>` from binary.
+    let matrix = read_detections(binary, rows, columns, &options)?;
+    if options.sanity_check {
+        check_box_encoding(&matrix)?;
+    }
+
+    let mut bboxes = Vec::new();
+    trace.dropped = extend_bboxes(&matrix, &options, &mut bboxes);
+    let final_bboxes = select_candidates(&mut bboxes, &mut trace);
+
+    encode_bboxes(env, final_bboxes, trace, &options)
+}
+
+// Runs NMS across several binaries, like the outputs of the heads of a multi-scale model exported
+// separately, without concatenating them: the candidates of each `{rows, columns}` binary are
+// parsed in turn and suppressed together, their `row` being the index in the concatenated detections.
+#[rustler::nif(name = "run_scales_nif")]
+fn run_scales<'a>(
+    env: Env<'a>,
+    binaries: Vec<Binary>,
+    shapes: Vec<(usize, usize)>,
+    options: NmsOptions
+) -> NifResult<Term<'a>> {
+    // the `{features, detections}` shapes with `transpose`
+    let features = |&(rows, columns): &(usize, usize)| if options.transpose { rows } else { columns };
+    if binaries.len() != shapes.len() || shapes.windows(2).any(|pair| features(&pair[0]) != features(&pair[1])) {
+        return Err(Error::BadArg);
+    }
+
+    let mut trace = Trace::default();
+    let mut bboxes = Vec::new();
+    let mut scale_bboxes = Vec::new();
+    let mut first_row = 0;
+    for (binary, &(rows, columns)) in binaries.iter().zip(&shapes) {
+        if options.transpose {
+            trace.dropped += read_bboxes_columnwise(binary.as_slice(), rows, columns, &options, &mut scale_bboxes)?;
+        } else {
+            let matrix = read_detections(binary.as_slice(), rows, columns, &options)?;
+            scale_bboxes.clear();
+            trace.dropped += extend_bboxes(&matrix, &options, &mut scale_bboxes);
+        }
+        for bbox in scale_bboxes.iter_mut() {
+            bbox.row += first_row;
+        }
+        bboxes.append(&mut scale_bboxes);
+        first_row += if options.transpose { columns } else { rows };
+    }
+
+    let final_bboxes = select(&options, &mut bboxes, &mut trace);
+    encode_bboxes(env, final_bboxes, trace, &options)
+}
+
+// Runs NMS over a model output split in two binaries of `rows` detections: the `box_columns` f32
+// features of the boxes (`cx, cy, w, h`, plus the objectness) and the u8 class scores, quantized
+// to 0..255. The scores are dequantized to [0, 1] and appended to the box features of each row.
+#[rustler::nif(name = "run_quantized_nif")]
+fn run_quantized<'a>(
+    env: Env<'a>,
+    boxes: Binary,
+    scores: Binary,
+    rows: usize,
+    box_columns: usize,
+    options: NmsOptions
 ) -> NifResult<Term<'a>> {
-    
-    // load the matrix `Vec<Vec<f32>>` from binary.
-    let matrix = binary_to_matrix(&binary, rows, columns);
-
-    // transpose {rows, columns} to {columns, rows} if needed
-    let matrix = if transpose {
-        transpose_matrix(&matrix)
-    } else {
-        matrix
-    };
-    
-    let bboxes = matrix_to_bboxes(&matrix);
-    
-    //keep only the bboxes with prob > prob_threshold
-    let filtered_bboxes = bboxes.into_iter().filter(|b| b.prob >= prob_threshold).collect();
-
-    //run NMS
-    let final_bboxes = nms(&filtered_bboxes, iou_threshold);
-
-    //convert BBox to [cx, cy, w, h, prob, class_idx]
-    let result: Vec<Vec<f32>> = final_bboxes.into_iter().map(|bbox| {
-        vec![
-                bbox.cx as f32,
-                bbox.cy as f32,
-                bbox.w as f32,
-                bbox.h as f32,
-                bbox.prob,
-                bbox.class as f32,
-            ]
-    }).collect();
-
-    Ok(result.encode(env))
-}
-
-// tensor binary to a vector of vectors, each row of `row_size` bytes.
-fn binary_to_matrix(binary: &Binary, rows: usize, columns: usize) -> Vec<Vec<f32>> {
-    // Ensure the binary length is divisible by the row size
     let f32size = std::mem::size_of::<f32>();
-    let row_size = (columns * f32size).try_into().unwrap();
-    let total_size = rows * row_size;
-    assert!(binary.len() == total_size, "Binary size ({}) is less than the total size ({})", binary.len(), total_size);
-    assert!(binary.len() >= row_size, "Binary size ({}) is less than the row size ({})", binary.len(), row_size);
-    assert!(binary.len() % row_size == 0, "Binary size ({}) is not a multiple of the row size ({})", binary.len(), row_size);
-    
-    binary
+    let classes = scores.len().checked_div(rows).unwrap_or(0);
+    let shape_matches = box_columns == 4 + options.objectness as usize
+        && boxes.len() == rows * box_columns * f32size
+        && classes * rows == scores.len();
+    // same checks of the columns as `run_nif`, as an ArgumentError
+    let valid_features = checked_features(box_columns + classes, &options).map_err(|_| Error::BadArg)?;
+    if !shape_matches {
+        return Err(Error::BadArg);
+    }
+    let mut trace = Trace::default();
+    if rows == 0 || !valid_features {
+        return encode_bboxes(env, Vec::new(), trace, &options);
+    }
+
+    let matrix: Vec<Vec<f32>> = boxes
         .as_slice()
-        .chunks(row_size)
-        .map(|chunk| {
-            chunk
+        .chunks(box_columns * f32size)
+        .zip(scores.as_slice().chunks(classes))
+        .map(|(box_bytes, quantized)| {
+            box_bytes
                 .chunks(f32size)
                 .map(|bytes| f32::from_ne_bytes(bytes.try_into().unwrap()))
-                .collect::<Vec<f32>>()
+                .chain(quantized.iter().map(|&q| q as f32 / 255.0))
+                .collect()
         })
-        .collect()
+        .collect();
+
+    let final_bboxes = detect(&matrix, &options, &mut Vec::new(), &mut trace);
+    encode_bboxes(env, final_bboxes, trace, &options)
+}
+
+// `{raw_count, filtered_count, kept_count}` of a run: the parsed candidates, the ones above
+// `prob_threshold` and the ones kept by the NMS, without encoding the detections.
+#[rustler::nif(name = "counts_nif")]
+fn counts(binary: Binary, rows: usize, columns: usize, options: NmsOptions) -> NifResult<(usize, usize, usize)> {
+    let binary = payload(binary.as_slice(), rows, columns, &options);
+    let mut bboxes = Vec::new();
+    if options.transpose {
+        read_bboxes_columnwise(binary, rows, columns, &options, &mut bboxes)?;
+    } else {
+        let matrix = read_detections(binary, rows, columns, &options)?;
+        extend_bboxes(&matrix, &options, &mut bboxes);
+    }
+
+    let raw_count = bboxes.len();
+    let filtered_count = bboxes.iter().filter(|b| b.prob >= options.prob_threshold).count();
+    let kept_count = select(&options, &mut bboxes, &mut Trace::default()).len();
+    Ok((raw_count, filtered_count, kept_count))
+}
+
+// Rows inspected by `check_box_encoding`.
+const SANITY_CHECK_ROWS: usize = 64;
+
+// Cheap heuristic to catch the boxes in another encoding parsed as `cxcywh`, over rows
+// sampled across the whole matrix. A box with a non-positive size is implausible, and so is
+// a box larger than its center coordinates, always the case for `xyxy` boxes (`x2 > x1`)
+// and only for the few big boxes close to the top-left corner otherwise.
+fn check_box_encoding(matrix: &[Vec<f32>]) -> NifResult<()> {
+    let step = matrix.len().div_ceil(SANITY_CHECK_ROWS).max(1);
+    let sample: Vec<&Vec<f32>> = matrix.iter().step_by(step).collect();
+    let implausible = sample
+        .iter()
+        .filter(|row| {
+            let (cx, cy, w, h) = (row[0], row[1], row[2], row[3]);
+            w <= 0.0 || h <= 0.0 || (w > cx && h > cy)
+        })
+        .count();
+
+    if implausible * 2 > sample.len() {
+        return Err(Error::RaiseTerm(Box::new(format!(
+            "{} of {} sampled boxes have a non-positive size or are larger than their center coordinates, \
+             the boxes are likely not in the cx, cy, w, h format (xyxy?) or the transpose option is wrong",
+            implausible,
+            sample.len()
+        ))));
+    }
+    Ok(())
+}
+
+// Parses the candidates of the detections matrix and runs the NMS.
+// The candidates are parsed into `bboxes`, cleared first, so its capacity can be reused.
+pub(crate) fn detect(
+    matrix: &[Vec<f32>],
+    options: &NmsOptions,
+    bboxes: &mut Vec<BBox>,
+    trace: &mut Trace
+) -> Vec<BBox> {
+    bboxes.clear();
+    trace.dropped = extend_bboxes(matrix, options, bboxes);
+    select(options, bboxes, trace)
+}
+
+// Filters the parsed candidates by `prob_threshold` and `candidate_budget` and runs the NMS.
+pub(crate) fn select(options: &NmsOptions, bboxes: &mut Vec<BBox>, trace: &mut Trace) -> Vec<BBox> {
+    if options.min_results == 0 {
+        //keep only the bboxes with prob > prob_threshold
+        bboxes.retain(|b| b.prob >= options.prob_threshold);
+        if let Some(cell_size) = options.grid_max {
+            keep_cell_max(bboxes, cell_size);
+        }
+        if let Some(budget) = options.candidate_budget {
+            trace.truncated = truncate_to_budget(bboxes, budget);
+        }
+
+        //run NMS
+        return if options.dedup {
+            suppress(bboxes.clone(), options, &mut trace.suppressed)
+        } else {
+            nms(bboxes, options, &mut trace.suppressed)
+        };
+    }
+
+    // the filtered out bboxes are kept aside to backfill the result
+    let (mut filtered_bboxes, rejected_bboxes): (Vec<BBox>, Vec<BBox>) = bboxes
+        .drain(..)
+        .partition(|b| b.prob >= options.prob_threshold);
+    if let Some(cell_size) = options.grid_max {
+        keep_cell_max(&mut filtered_bboxes, cell_size);
+    }
+    if let Some(budget) = options.candidate_budget {
+        trace.truncated = truncate_to_budget(&mut filtered_bboxes, budget);
+    }
+
+    let mut final_bboxes = suppress(filtered_bboxes, options, &mut trace.suppressed);
+    backfill(&mut final_bboxes, rejected_bboxes, options);
+    final_bboxes
 }
 
-fn matrix_to_bboxes(matrix: &Vec<Vec<f32>>) -> Vec<BBox> {
-    matrix
+// Runs the NMS over detections in the list format returned by `run_nif`, like the
+// concatenated results of two configurations. They were already filtered by their own
+// `prob_threshold`, so it isn't applied again. The row of each detection is its index in the
+// list, the identity of the candidates for `min_support` and `force_keep_threshold`.
+#[rustler::nif(name = "merge_nif")]
+fn merge<'a>(env: Env<'a>, detections: Vec<Vec<f32>>, options: NmsOptions) -> NifResult<Term<'a>> {
+    let detection_size = if options.obb { 7 } else { 6 };
+    let bboxes = detections
         .iter()
-        .map(|row| bbox_from_row(&row))
-        .collect()
+        .enumerate()
+        .map(|(row, detection)| {
+            // a NaN prob can't be sorted, an invalid class id would be wrapped
+            if detection.len() != detection_size || !detection[4].is_finite() {
+                return Err(Error::BadArg);
+            }
+            let mut bbox = bbox_from_row(&detection[..4], &[]).ok_or(Error::BadArg)?;
+            bbox.prob = detection[4];
+            bbox.class = class_from_f32(detection[5]).ok_or(Error::BadArg)?;
+            if options.obb {
+                bbox.angle = detection[6];
+            }
+            bbox.row = row;
+            Ok(bbox)
+        })
+        .collect::<NifResult<Vec<BBox>>>()?;
+
+    let mut trace = Trace::default();
+    let final_bboxes = suppress(bboxes, &options, &mut trace.suppressed);
+    encode_bboxes(env, final_bboxes, trace, &options)
 }
 
-fn bbox_from_row(row: &Vec<f32>) -> BBox {    
-    let cx = row[0].round() as i32;
-    let cy = row[1].round() as i32;
-    let w = row[2].round() as i32;
-    let h = row[3].round() as i32;
+// Packs detections in the list format back into a model output binary, the inverse of the parsing,
+// to build fixtures for `run_nif`. Each detection is a row of `[cx, cy, w, h]`, the objectness
+// (1.0, with `objectness`) and the `classes` probabilities, all 0.0 but `prob` in the `class_idx`
+// column, then the angle with `obb`. The rows are laid out as `{features, detections}` with `transpose`.
+#[rustler::nif(name = "pack_nif")]
+fn pack<'a>(env: Env<'a>, detections: Vec<Vec<f32>>, classes: usize, options: NmsOptions) -> NifResult<Binary<'a>> {
+    let detection_size = if options.obb { 7 } else { 6 };
+    let features = 4 + options.objectness as usize + classes + options.obb as usize;
+    let f32size = std::mem::size_of::<f32>();
+
+    let mut binary = rustler::OwnedBinary::new(detections.len() * features * f32size).ok_or(Error::BadArg)?;
+    let data = binary.as_mut_slice();
+    for (i, detection) in detections.iter().enumerate() {
+        let class = detection.get(5).copied().and_then(class_from_f32).map(usize::from);
+        if detection.len() != detection_size || class.is_none_or(|class| class >= classes) {
+            return Err(Error::BadArg);
+        }
+
+        let mut row = detection[..4].to_vec();
+        if options.objectness {
+            row.push(1.0);
+        }
+        let class_start = row.len();
+        row.resize(class_start + classes, 0.0);
+        row[class_start + class.unwrap()] = detection[4];
+        if options.obb {
+            row.push(detection[6]);
+        }
+
+        for (feature, value) in row.into_iter().enumerate() {
+            let index = if options.transpose { feature * detections.len() + i } else { i * features + feature };
+            data[index * f32size..(index + 1) * f32size].copy_from_slice(&value.to_ne_bytes());
+        }
+    }
+
+    Ok(binary.release(env))
+}
+
+fn read_detections(binary: &[u8], rows: usize, columns: usize, options: &NmsOptions) -> NifResult<Vec<Vec<f32>>> {
+    let mut matrix = Vec::new();
+    read_detections_into(binary, rows, columns, options, &mut matrix)?;
+    Ok(matrix)
+}
+
+// Reads the `[row_offset, row_offset + row_count)` detections window of the tensor into `matrix`,
+// transposing {rows, columns} to {columns, rows} if needed.
+// Only the bytes of the window are parsed. The rows of `matrix` are cleared and refilled,
+// so their capacity is reused when a matrix of the same shape is read over and over.
+pub(crate) fn read_detections_into(
+    binary: &[u8],
+    rows: usize,
+    columns: usize,
+    options: &NmsOptions,
+    matrix: &mut Vec<Vec<f32>>
+) -> NifResult<()> {
+    let f32size = std::mem::size_of::<f32>();
+    let Some((row_offset, row_count)) = detections_window(binary, rows, columns, options)? else {
+        matrix.clear();
+        return Ok(());
+    };
+
+    matrix.resize_with(row_count, Vec::new);
+    for row in matrix.iter_mut() {
+        row.clear();
+    }
+
+    if options.transpose {
+        // each row is a feature, the window is a range of columns
+        let window = row_offset * f32size..(row_offset + row_count) * f32size;
+        for feature_row in binary.chunks(columns * f32size) {
+            for (row, bytes) in matrix.iter_mut().zip(feature_row[window.clone()].chunks(f32size)) {
+                row.push(f32_from_bytes(bytes));
+            }
+        }
+    } else {
+        let row_size = columns * f32size;
+        let window = row_offset * row_size..(row_offset + row_count) * row_size;
+        for (row, chunk) in matrix.iter_mut().zip(binary[window].chunks(row_size)) {
+            row.extend(chunk.chunks(f32size).map(f32_from_bytes));
+        }
+    }
+    Ok(())
+}
+
+// The `(row_offset, row_count)` window of the detections to parse, `None` for the degenerate shapes.
+// A `BadArg` error when the window end overflows.
+fn detections_window(
+    binary: &[u8],
+    rows: usize,
+    columns: usize,
+    options: &NmsOptions
+) -> NifResult<Option<(usize, usize)>> {
+    assert_binary_size(binary, rows, columns);
+
+    let detections = if options.transpose { columns } else { rows };
+    let row_offset = options.row_offset;
+    let row_count = options.row_count.unwrap_or(detections.saturating_sub(row_offset));
+    let row_end = row_offset.checked_add(row_count).ok_or(Error::BadArg)?;
+    assert!(row_end <= detections, "Rows window [{}, {}) is out of the {} detections", row_offset, row_end, detections);
+
+    // degenerate shapes: no detections, or detections without any class probability
+    let features = if options.transpose { rows } else { columns };
+    if !valid_features(features, options) || row_count == 0 {
+        return Ok(None);
+    }
+    Ok(Some((row_offset, row_count)))
+}
+
+// Whether the detections have at least a class probability, after the optional columns.
+// Panics on the `valid_columns`, `score_column` or `class_column` out of the `features`.
+pub(crate) fn valid_features(features: usize, options: &NmsOptions) -> bool {
+    checked_features(features, options).unwrap_or_else(|message| panic!("{}", message))
+}
+
+// `valid_features`, with the message of the columns out of the `features` as an error.
+fn checked_features(features: usize, options: &NmsOptions) -> Result<bool, String> {
+    let features = match options.valid_columns {
+        Some(valid_columns) if valid_columns > features => {
+            return Err(format!("valid_columns ({}) is above the {} features", valid_columns, features));
+        }
+        Some(valid_columns) => valid_columns,
+        None => features,
+    };
+    let min_features = 5
+        + options.obb as usize
+        + options.objectness as usize
+        + options.argmaxed as usize
+        + options.group_column as usize;
+    if features < min_features {
+        return Ok(false);
+    }
+    // the `obb` angle and the `group_column` are the last features, after the score and class columns
+    let score_features = features - options.obb as usize - options.group_column as usize;
+    for column in [options.score_column, options.class_column].into_iter().flatten() {
+        if column >= score_features {
+            return Err(format!("Column {} is out of the {} features", column, score_features));
+        }
+    }
+    Ok(true)
+}
+
+// Parses the candidates of a `{features, detections}` tensor (`transpose: true`) into `bboxes`,
+// gathering the features of each detection at their stride in the binary into a single row buffer.
+// Same candidates as `read_detections_into` and `extend_bboxes`, without the transposed matrix.
+// Returns the number of malformed rows skipped.
+pub(crate) fn read_bboxes_columnwise(
+    binary: &[u8],
+    rows: usize,
+    columns: usize,
+    options: &NmsOptions,
+    bboxes: &mut Vec<BBox>
+) -> NifResult<usize> {
+    bboxes.clear();
+    let Some((row_offset, row_count)) = detections_window(binary, rows, columns, options)? else {
+        return Ok(0);
+    };
+
+    let f32size = std::mem::size_of::<f32>();
+    let multi_label = options.multi_label && options.score_type == ScoreType::Sigmoid;
+    // the padding features after `valid_columns` are not read
+    let mut row = vec![0.0_f32; options.valid_columns.unwrap_or(rows)];
+    let mut dropped = 0;
+    for i in row_offset..row_offset + row_count {
+        for (feature, value) in row.iter_mut().enumerate() {
+            let start = (feature * columns + i) * f32size;
+            *value = f32_from_bytes(&binary[start..start + f32size]);
+        }
+        if !extend_row_bboxes(&row, i, options, multi_label, bboxes) {
+            dropped += 1;
+        }
+    }
+    Ok(dropped)
+}
+
+// Number of the rows identical, bit for bit, to a previous row of the detections window.
+// It's a diagnostic of the upstream bugs, like a tile concatenated twice, the rows are still parsed.
+pub(crate) fn count_duplicate_rows(
+    binary: &[u8],
+    rows: usize,
+    columns: usize,
+    options: &NmsOptions
+) -> NifResult<usize> {
+    let Some((row_offset, row_count)) = detections_window(binary, rows, columns, options)? else {
+        return Ok(0);
+    };
+
+    let f32size = std::mem::size_of::<f32>();
+    let features = if options.transpose { rows } else { columns };
+    let mut seen: HashSet<Vec<u32>> = HashSet::with_capacity(row_count);
+    let duplicates = (row_offset..row_offset + row_count)
+        .filter(|&i| {
+            let row: Vec<u32> = (0..features)
+                .map(|feature| {
+                    let index = if options.transpose { feature * columns + i } else { i * columns + feature };
+                    u32::from_ne_bytes(binary[index * f32size..(index + 1) * f32size].try_into().unwrap())
+                })
+                .collect();
+            !seen.insert(row)
+        })
+        .count();
+    Ok(duplicates)
+}
+
+// The `{rows, columns}` tensor starting `byte_offset` bytes into the binary, like the payload of
+// a framed buffer, without copying it. With an offset the bytes after the tensor are ignored,
+// without one the binary must be the tensor.
+pub(crate) fn payload<'b>(binary: &'b [u8], rows: usize, columns: usize, options: &NmsOptions) -> &'b [u8] {
+    if options.byte_offset == 0 {
+        return binary;
+    }
+    let end = binary_size(rows, columns).and_then(|size| size.checked_add(options.byte_offset));
+    match end {
+        Some(end) if end <= binary.len() => &binary[options.byte_offset..end],
+        _ => panic!(
+            "Tensor at byte_offset {} is out of the binary ({} bytes)", options.byte_offset, binary.len()
+        ),
+    }
+}
+
+fn assert_binary_size(binary: &[u8], rows: usize, columns: usize) {
+    // Ensure the binary length matches the shape, zero rows or columns means an empty binary
+    let total_size = binary_size(rows, columns).unwrap_or(usize::MAX);
+    assert!(binary.len() == total_size, "Binary size ({}) is different from the total size ({})", binary.len(), total_size);
+}
+
+// Bytes of a `{rows, columns}` f32 tensor, `None` when it overflows.
+fn binary_size(rows: usize, columns: usize) -> Option<usize> {
+    let f32size = std::mem::size_of::<f32>();
+    let row_size = columns.checked_mul(f32size)?;
+    rows.checked_mul(row_size)
+}
+
+// Checks the type and the size of a `{rows, columns}` tensor of `binary_len` bytes without
+// reading it, so a misconfigured model export fails at startup rather than on the first frame.
+#[rustler::nif(name = "validate_nif")]
+fn validate<'a>(
+    env: Env<'a>,
+    binary_len: usize,
+    rows: usize,
+    columns: usize,
+    dtype: (Atom, usize)
+) -> NifResult<Term<'a>> {
+    let (kind, bits) = dtype;
+    let error = |reason: String| Ok((rustler::types::atom::error(), reason).encode(env));
+
+    if kind != atoms::f() || bits != 32 {
+        let kind = kind.to_term(env).atom_to_string()?;
+        return error(format!("unsupported tensor type {{:{}, {}}}, only {{:f, 32}} is supported", kind, bits));
+    }
+    match binary_size(rows, columns) {
+        None => error(format!("shape {{{}, {}}} is too large", rows, columns)),
+        Some(total_size) if total_size != binary_len => error(format!(
+            "binary size ({}) is different from the size of a {{{}, {}}} f32 tensor ({})",
+            binary_len, rows, columns, total_size
+        )),
+        Some(_) => Ok(rustler::types::atom::ok().encode(env)),
+    }
+}
+
+// The Cargo features the NIF was compiled with.
+#[derive(NifMap)]
+struct Features {
+    alloc_count: bool,
+    parallel: bool,
+    threads: bool,
+}
+
+#[derive(NifMap)]
+struct BuildInfo {
+    version: String,
+    // debug build, with the `debug_assert!` checks like the `input_sorted` order
+    debug: bool,
+    features: Features,
+}
+
+// The build configuration of the loaded NIF, to check at runtime that the right artifact is deployed.
+#[rustler::nif(name = "info_nif")]
+fn info() -> BuildInfo {
+    BuildInfo {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        debug: cfg!(debug_assertions),
+        features: Features {
+            alloc_count: cfg!(feature = "alloc_count"),
+            parallel: cfg!(feature = "parallel"),
+            threads: cfg!(feature = "threads"),
+        },
+    }
+}
+
+fn f32_from_bytes(bytes: &[u8]) -> f32 {
+    f32::from_ne_bytes(bytes.try_into().unwrap())
+}
+
+// Appends the candidates of each row of the matrix to `bboxes`.
+// Returns the number of malformed rows skipped.
+fn extend_bboxes(matrix: &[Vec<f32>], options: &NmsOptions, bboxes: &mut Vec<BBox>) -> usize {
+    let multi_label = options.multi_label && options.score_type == ScoreType::Sigmoid;
+
+    let mut dropped = 0;
+    for (i, row) in matrix.iter().enumerate() {
+        if !extend_row_bboxes(row, options.row_offset + i, options, multi_label, bboxes) {
+            dropped += 1;
+        }
+    }
+    dropped
+}
+
+// Appends the candidates of the detection `row`, the `row_index`-th of the tensor.
+// Returns false for a malformed row, skipped: NaN or infinite coordinates, a negative size,
+// or an invalid group or class id.
+fn extend_row_bboxes(
+    row: &[f32],
+    row_index: usize,
+    options: &NmsOptions,
+    multi_label: bool,
+    bboxes: &mut Vec<BBox>
+) -> bool {
+    // the zero padding of the rows (like the TensorRT alignment of the classes) is ignored
+    let row = match options.valid_columns {
+        Some(valid_columns) => &row[..valid_columns.min(row.len())],
+        None => row,
+    };
+    // with `group_column` the group id of the detection (like its tile) is the last column, as a float
+    let (row, group) = if options.group_column {
+        match class_from_f32(row[row.len() - 1]) {
+            Some(group) => (&row[..row.len() - 1], group),
+            None => return false,
+        }
+    } else {
+        (row, 0)
+    };
+    // oriented boxes have the angle in the last column, after the class probabilities
+    let (row, angle) = if options.obb {
+        (&row[..row.len() - 1], row[row.len() - 1])
+    } else {
+        (row, 0.0)
+    };
+    // pre-argmaxed rows have the score and then the class id, as a float
+    let (row, argmaxed_class) = if options.argmaxed {
+        match class_from_f32(row[row.len() - 1]) {
+            Some(class) => (&row[..row.len() - 1], Some(class)),
+            None => return false,
+        }
+    } else {
+        (row, None)
+    };
+    // with `score_column` and `class_column` the final score and the class id are read from
+    // these columns, without scanning the class probabilities
+    let precomputed = match (options.score_column, options.class_column) {
+        (Some(score_column), Some(class_column)) => match class_from_f32(row[class_column]) {
+            Some(class) => Some((score_column, class)),
+            None => return false,
+        },
+        _ => None,
+    };
+    let argmaxed_class = argmaxed_class.or(precomputed.map(|(_, class)| class));
+    // with objectness (YOLOv5) the class probabilities start after it
+    let (objectness, class_probs) = if let Some((score_column, _)) = precomputed {
+        (None, std::slice::from_ref(&row[score_column]))
+    } else if options.objectness {
+        (Some(row[4]), &row[5..])
+    } else {
+        (None, &row[4..])
+    };
+    // with `objectness_classes` only the classes in the ranges are gated by the objectness
+    let gated = |class: u16| {
+        options
+            .objectness_classes
+            .as_ref()
+            .is_none_or(|ranges| ranges.iter().any(|&(first, last)| (first..=last).contains(&class)))
+    };
+    let prior = prior_weight(row[0], row[1], options) * area_weight(row[2] * row[3], options);
+    let score = |class: u16, class_prob: f32| {
+        let prob = match objectness {
+            Some(objectness) if gated(class) => options.score_combine.combine(objectness, class_prob),
+            _ => class_prob,
+        };
+        calibrate(prob, options) * prior
+    };
+
+    // NaN, infinite or huge coordinates (like an overflowing exp of the wh decoding) are
+    // rejected by `bbox_from_row` instead of becoming nonsense boxes
+    if row[2] < 0.0 || row[3] < 0.0 {
+        return false;
+    }
+    let Some(mut bbox) = bbox_from_row(&row[..4], class_probs) else {
+        return false;
+    };
+    // the boxes larger than the image are often decoding errors, distorting the IoU
+    if let Some((width, height)) = options.image_size {
+        match options.oversize_policy {
+            OversizePolicy::Keep => {}
+            OversizePolicy::Clamp => {
+                bbox.w = bbox.w.min(width);
+                bbox.h = bbox.h.min(height);
+            }
+            OversizePolicy::Drop if bbox.w > width || bbox.h > height => return true,
+            OversizePolicy::Drop => {}
+        }
+    }
+    bbox.angle = angle;
+    bbox.row = row_index;
+    bbox.group = group;
+    if let Some(class) = argmaxed_class {
+        bbox.class = class;
+    }
+
+    // independent sigmoids can have several high classes, the margin only makes sense
+    // when a single class is taken for each row
+    if !multi_label && options.margin_threshold > 0.0
+        && class_margin(class_probs, bbox.prob) < options.margin_threshold {
+        return true;
+    }
+    // near-exact ties of the best classes, where the argmax would pick the first one arbitrarily
+    let tied = |epsilon: f32| class_margin(class_probs, bbox.prob) <= epsilon;
+    if !multi_label && class_probs.len() > 1 && options.class_tie_epsilon.is_some_and(tied) {
+        return true;
+    }
+
+    if options.class_probs || options.class_scores.is_some() {
+        bbox.class_probs = class_probs.to_vec();
+    }
+    if options.top_k_classes > 1 {
+        bbox.top_classes = top_classes(class_probs, options.top_k_classes);
+    }
+
+    // the small boxes are noise for some classes, the minimum side is checked for each candidate class
+    let large_enough = |class: u16| {
+        let min_size = options
+            .class_min_sizes
+            .as_ref()
+            .and_then(|sizes| sizes.get(&class).copied())
+            .unwrap_or(options.min_size);
+        row[2] >= min_size && row[3] >= min_size
+    };
+    let kept_class = |class: u16| {
+        options.keep_classes.as_ref().is_none_or(|classes| classes.contains(&class)) && large_enough(class)
+    };
+
+    if multi_label {
+        // a box for each other class above the threshold, the argmax box is pushed below
+        for (class, &class_prob) in class_probs.iter().enumerate() {
+            let prob = score(class as u16, class_prob);
+            if prob >= options.prob_threshold && class as u16 != bbox.class && kept_class(class as u16) {
+                bboxes.push(BBox { prob, class: class as u16, ..bbox.clone() });
+            }
+        }
+    }
+    if kept_class(bbox.class) {
+        bbox.prob = score(bbox.class, bbox.prob);
+        // a NaN score (like a NaN objectness) can't be ranked, even by the `min_results` backfill
+        if !bbox.prob.is_nan() {
+            bboxes.push(bbox);
+        }
+    }
+    true
+}
+
+// Weight of the `prior_grid` cell of the `(cx, cy)` center, 1.0 for the centers out of
+// the `image_size` image or without a grid.
+fn prior_weight(cx: f32, cy: f32, options: &NmsOptions) -> f32 {
+    let (Some((grid_rows, grid_columns, weights)), Some((width, height))) = (&options.prior_grid, options.image_size)
+    else {
+        return 1.0;
+    };
+    if !(0.0..width as f32).contains(&cx) || !(0.0..height as f32).contains(&cy) {
+        return 1.0;
+    }
+    let column = (cx / width as f32 * *grid_columns as f32) as usize;
+    let row = (cy / height as f32 * *grid_rows as f32) as usize;
+    weights[row.min(grid_rows - 1) * grid_columns + column.min(grid_columns - 1)]
+}
+
+// Weight of the first `area_weights` step `(max_area, weight)` with the box `area` within its
+// `max_area`, 1.0 for the boxes larger than all the steps or without `area_weights`.
+fn area_weight(area: f32, options: &NmsOptions) -> f32 {
+    options
+        .area_weights
+        .as_ref()
+        .and_then(|steps| steps.iter().find(|&&(max_area, _)| area <= max_area))
+        .map_or(1.0, |&(_, weight)| weight)
+}
+
+// The `prob` remapped by the piecewise-linear `calibration` curve, `(input, output)` breakpoints
+// sorted by input. The probabilities out of the curve take the output of its nearest end.
+// A NaN stays NaN, to be dropped, instead of taking the output of the last breakpoint.
+fn calibrate(prob: f32, options: &NmsOptions) -> f32 {
+    let Some(points) = options.calibration.as_deref().filter(|_| !prob.is_nan()) else {
+        return prob;
+    };
+    match points.iter().position(|&(input, _)| prob < input) {
+        Some(0) => points[0].1,
+        None => points[points.len() - 1].1,
+        Some(i) => {
+            let ((x0, y0), (x1, y1)) = (points[i - 1], points[i]);
+            y0 + (prob - x0) / (x1 - x0) * (y1 - y0)
+        }
+    }
+}
+
+// Class id of a pre-argmaxed row, rounded to the nearest integer.
+// `None` for the NaN, negative or too large ids, the row is skipped.
+fn class_from_f32(value: f32) -> Option<u16> {
+    let class = value.round();
+    (class >= 0.0 && class <= u16::MAX as f32).then_some(class as u16)
+}
+
+// The `k` most probable `(class, prob)` of the row, by `prob` descending, the first class
+// winning the ties and the NaN skipped like the argmax. Kept in a small sorted buffer of `k`
+// entries while scanning the classes, instead of sorting all of them.
+fn top_classes(class_probs: &[f32], k: usize) -> Vec<(u16, f32)> {
+    let mut top: Vec<(u16, f32)> = Vec::with_capacity(k + 1);
+    for (class, &prob) in class_probs.iter().enumerate() {
+        if prob.is_nan() || (top.len() == k && top[k - 1].1 >= prob) {
+            continue;
+        }
+        let position = top.partition_point(|&(_, top_prob)| top_prob >= prob);
+        top.insert(position, (class as u16, prob));
+        top.truncate(k);
+    }
+    top
+}
+
+// Difference between the highest and the second highest class probability.
+fn class_margin(class_probs: &[f32], max_prob: f32) -> f32 {
+    let mut max_found = false;
+    let second_prob = class_probs.iter().fold(f32::MIN, |second, &prob| {
+        if prob == max_prob && !max_found {
+            max_found = true;
+            second
+        } else {
+            second.max(prob)
+        }
+    });
+    if class_probs.len() < 2 {
+        max_prob
+    } else {
+        max_prob - second_prob
+    }
+}
+
+fn filter_bboxes(bboxes: Vec<BBox>, prob_threshold: f32) -> Vec<BBox> {
+    bboxes.into_iter().filter(|b| b.prob >= prob_threshold).collect()
+}
+
+// The "same box" of `dedup`, the temporal matching and the fingerprint with `match_tolerance`:
+// `cx`, `cy`, `w` and `h` differ at most by `tolerance`, whatever the class.
+pub(crate) fn boxes_approx_equal(a: &BBox, b: &BBox, tolerance: f32) -> bool {
+    [(a.cx, b.cx), (a.cy, b.cy), (a.w, b.w), (a.h, b.h)]
+        .iter()
+        .all(|&(a, b)| (a - b).abs() as f32 <= tolerance)
+}
+
+// Keeps only the most probable box among the boxes of the same class
+// whose `cx`, `cy`, `w` and `h` differ at most by `tolerance`.
+// The exact duplicates keep the order of their first occurrence, for the stable sorts after.
+fn dedup_bboxes(bboxes: Vec<BBox>, tolerance: f32) -> Vec<BBox> {
+    if tolerance <= 0.0 {
+        // the index in `unique` of each key
+        let mut indexes: HashMap<(u16, i32, i32, i32, i32), usize> = HashMap::new();
+        let mut unique: Vec<BBox> = Vec::new();
+        for bbox in bboxes {
+            let key = (bbox.class, bbox.cx, bbox.cy, bbox.w, bbox.h);
+            match indexes.get(&key) {
+                Some(&i) if unique[i].prob >= bbox.prob => {}
+                Some(&i) => unique[i] = bbox,
+                None => {
+                    indexes.insert(key, unique.len());
+                    unique.push(bbox);
+                }
+            }
+        }
+        return unique;
+    }
+
+    let mut sorted_bboxes = bboxes;
+    sorted_bboxes.sort_by(|a, b| b.prob.total_cmp(&a.prob));
+
+    let mut kept_bboxes: Vec<BBox> = Vec::new();
+    for bbox in sorted_bboxes {
+        let is_duplicate = kept_bboxes
+            .iter()
+            .any(|kb| kb.class == bbox.class && boxes_approx_equal(kb, &bbox, tolerance));
+        if !is_duplicate {
+            kept_bboxes.push(bbox);
+        }
+    }
+    kept_bboxes
+}
+
+// Largest coordinate of a box, in the model coordinates. Far above any image size, and low
+// enough for the corners (`cx + w / 2`) to fit an i32 and the areas an i64.
+const MAX_COORDINATE: f32 = 16_777_216.0;
+
+// The rounded coordinate, `None` for NaN, infinite or out of `MAX_COORDINATE` values that
+// the `as i32` cast would saturate into a huge valid-looking coordinate.
+fn coordinate(value: f32) -> Option<i32> {
+    let rounded = value.round();
+    (rounded.abs() <= MAX_COORDINATE).then_some(rounded as i32)
+}
+
+impl BBox {
+    // An axis-aligned box, for the callers of `nms_with`.
+    pub fn new(cx: i32, cy: i32, w: i32, h: i32, prob: f32, class: u16) -> BBox {
+        BBox {
+            prob,
+            class,
+            cx,
+            cy,
+            w,
+            h,
+            angle: 0.0,
+            row: 0,
+            clamped: false,
+            class_probs: Vec::new(),
+            top_classes: Vec::new(),
+            track_id: None,
+            group: 0,
+        }
+    }
+}
+
+// `coords` are `[cx, cy, w, h]`, the class is the argmax of `class_probs`.
+// `None` when a coordinate is out of range, see `coordinate`.
+fn bbox_from_row(coords: &[f32], class_probs: &[f32]) -> Option<BBox> {
+    let cx = coordinate(coords[0])?;
+    let cy = coordinate(coords[1])?;
+    let w = coordinate(coords[2])?;
+    let h = coordinate(coords[3])?;
 
 
     //find the class with the highest probability
-    let (max_prob, class) = row[4..].iter().enumerate()
+    let (max_prob, class) = class_probs.iter().enumerate()
         .fold((f32::MIN, 0), |(max_prob, max_class), (i, &prob)| {
             if prob > max_prob {
-                (prob, i as u16)                
+                (prob, i as u16)
             } else {
                 (max_prob, max_class)
             }
         });
 
-    BBox {
+    Some(BBox {
         prob: max_prob,
         class,
         cx,
         cy,
         w,
-        h
+        h,
+        angle: 0.0,
+        row: 0,
+        clamped: false,
+        class_probs: Vec::new(),
+        top_classes: Vec::new(),
+        track_id: None,
+        group: 0,
+    })
+}
+
+// Removes the duplicated boxes, if requested, and runs NMS.
+fn suppress(bboxes: Vec<BBox>, options: &NmsOptions, suppressed: &mut Vec<Suppressed>) -> Vec<BBox> {
+    //collapse duplicated boxes, cheaper than letting NMS remove them
+    if options.dedup {
+        return nms(&dedup_bboxes(bboxes, options.dedup_tolerance()), options, suppressed);
     }
+    nms(&bboxes, options, suppressed)
 }
 
-fn nms(bboxes: &Vec<BBox>, iou_threshold: f32) -> Vec<BBox> {
+// With `suppressed`, the boxes removed by hard NMS and Soft-NMS are appended to `suppressed`.
+fn nms(bboxes: &[BBox], options: &NmsOptions, suppressed: &mut Vec<Suppressed>) -> Vec<BBox> {
     let mut final_boxes: Vec<BBox> = Vec::new();
     let mut class_kept_boxes: Vec<BBox> = Vec::new();
+    let mut class_unions: Vec<Corners> = Vec::new();
+    let union = options.geometry == Geometry::Union;
 
-    for class in get_classes(&bboxes) {
-        let class_boxes = sorted_boxes_filtered_by_class(&bboxes, class);
-        class_kept_boxes.clear();
+    // the `never_suppress` boxes and the trusted ones at or above `band_high` bypass the
+    // suppression, appended as they are after the others. The boxes below `band_low` are dropped.
+    let in_band = |bbox: &BBox| options.band_low.is_none_or(|low| bbox.prob >= low);
+    let bypassed = |bbox: &BBox| {
+        options.never_suppress.as_ref().is_some_and(|classes| classes.contains(&bbox.class))
+            || options.band_high.is_some_and(|high| bbox.prob >= high)
+    };
+    let (bypassed_bboxes, suppressible_bboxes): (Vec<BBox>, Vec<BBox>);
+    let bboxes = if options.never_suppress.is_some() || options.band_low.is_some() || options.band_high.is_some() {
+        (bypassed_bboxes, suppressible_bboxes) =
+            bboxes.iter().filter(|bbox| in_band(bbox)).cloned().partition(|bbox| bypassed(bbox));
+        &suppressible_bboxes[..]
+    } else {
+        bypassed_bboxes = Vec::new();
+        bboxes
+    };
 
-        for bbox in &class_boxes {
-            let mut max_iou: f32 = 0.0;
-            for kb in &class_kept_boxes {
-                max_iou = calc_iou(&bbox, kb).max(max_iou);
+    let capped_bboxes;
+    let bboxes = match options.max_candidates_per_class {
+        Some(cap) => {
+            capped_bboxes = cap_per_class(bboxes, cap);
+            &capped_bboxes[..]
+        }
+        None => bboxes,
+    };
+
+    // with `group_column` the boxes are only suppressed within their group (and class)
+    let groups: Vec<Vec<BBox>> = if options.group_column {
+        let mut group_ids: Vec<u16> = bboxes.iter().map(|b| b.group).collect();
+        group_ids.sort_unstable();
+        group_ids.dedup();
+        group_ids
+            .into_iter()
+            .flat_map(|group| {
+                let group_boxes: Vec<BBox> = bboxes.iter().filter(|b| b.group == group).cloned().collect();
+                class_groups(&group_boxes, options)
+            })
+            .collect()
+    } else {
+        class_groups(bboxes, options)
+    };
+
+    // the hard NMS verdicts of every group, the expensive part, can be computed in parallel
+    // the f32 and the fixed-point IoU of the axis-aligned boxes are computed over a struct of arrays
+    let mut hard_verdicts = if options.method == NmsMethod::Hard && options.iou_threshold > 0.0 {
+        map_groups(&groups, options.threads, |class_boxes| {
+            if !options.obb && options.precision == Precision::F32 {
+                greedy_verdicts(class_boxes, IouColumns::new(options))
+            } else if !options.obb && options.precision == Precision::Fixed {
+                greedy_verdicts(class_boxes, FixedIouColumns::new(options))
+            } else {
+                let kept_boxes = KeptRefs::new(|iou| suppresses(iou, options), |a, b| overlap(a, b, options));
+                greedy_verdicts(class_boxes, kept_boxes)
             }
-            if max_iou <= iou_threshold {
-                class_kept_boxes.push(bbox.clone());
+        })
+    } else {
+        Vec::new()
+    }
+    .into_iter();
+
+    for class_boxes in groups {
+        match options.method {
+            // threshold 0: any overlap suppresses, the most probable box is the only one kept
+            NmsMethod::Hard if options.iou_threshold <= 0.0 => {
+                let mut class_boxes = class_boxes.into_iter();
+                if let Some(mut kept) = class_boxes.next() {
+                    if union || options.suppressed {
+                        let mut corners = Corners::of(&kept);
+                        for bbox in class_boxes {
+                            let iou = overlap(&bbox, &kept, options);
+                            if union && iou > 0.0 {
+                                corners.enclose(&Corners::of(&bbox));
+                            }
+                            if options.suppressed {
+                                suppressed.push(Suppressed { bbox, by: final_boxes.len(), iou });
+                            }
+                        }
+                        if union {
+                            corners.apply(&mut kept);
+                        }
+                    }
+                    final_boxes.push(kept);
+                }
             }
+            NmsMethod::Hard => {
+                class_kept_boxes.clear();
+                class_unions.clear();
+
+                let verdicts = hard_verdicts.next().unwrap();
+                // the unions are of the original geometry of the kept boxes,
+                // they don't change which boxes are suppressed
+                for (bbox, verdict) in class_boxes.into_iter().zip(verdicts) {
+                    match verdict {
+                        None => {
+                            if union {
+                                class_unions.push(Corners::of(&bbox));
+                            }
+                            class_kept_boxes.push(bbox);
+                        }
+                        Some((suppressing, iou)) => {
+                            if union {
+                                class_unions[suppressing].enclose(&Corners::of(&bbox));
+                            }
+                            if options.suppressed {
+                                let by = final_boxes.len() + suppressing;
+                                suppressed.push(Suppressed { bbox, by, iou });
+                            }
+                        }
+                    }
+                }
+                for (kb, corners) in class_kept_boxes.iter_mut().zip(&class_unions) {
+                    corners.apply(kb);
+                }
+                final_boxes.append(&mut class_kept_boxes);
+            }
+            NmsMethod::Soft => {
+                let mut kept_boxes = soft_nms(class_boxes, options, final_boxes.len(), suppressed);
+                final_boxes.append(&mut kept_boxes);
+            }
+            NmsMethod::Matrix => final_boxes.append(&mut matrix_nms(class_boxes, options)),
+            NmsMethod::Confluence => final_boxes.append(&mut confluence(class_boxes, options)),
+            NmsMethod::Point => final_boxes.append(&mut point_nms(class_boxes, options)),
         }
-        final_boxes.extend(class_kept_boxes.drain(..));
     }
 
+    if options.min_support > 0 {
+        final_boxes.retain(|kept| support(kept, bboxes, options) >= options.min_support);
+    }
+
+    // at that confidence, overlapping boxes are likely distinct objects: the suppressed ones are
+    // appended to the survivors, without the exact repeats of a kept box. A candidate is identified
+    // by its row and class (the `multi_label` boxes of a row have different classes), `merge`
+    // numbers its detections as rows
+    if let Some(force_keep_threshold) = options.force_keep_threshold {
+        let geometry = |bbox: &BBox| (bbox.class, bbox.cx, bbox.cy, bbox.w, bbox.h);
+        for bbox in bboxes.iter().filter(|bbox| bbox.prob >= force_keep_threshold) {
+            let kept = final_boxes.iter().any(|kept| {
+                (kept.row, kept.class) == (bbox.row, bbox.class) || geometry(kept) == geometry(bbox)
+            });
+            if !kept {
+                final_boxes.push(bbox.clone());
+            }
+        }
+    }
+
+    final_boxes.extend(bypassed_bboxes);
     final_boxes
 }
 
-fn calc_iou(a: &BBox, b: &BBox) -> f32 {
-    // Calculate the coordinates of the intersection rectangle
-    let x1 = (a.cx - a.w / 2).max(b.cx - b.w / 2);
-    let y1 = (a.cy - a.h / 2).max(b.cy - b.h / 2);
-    let x2 = (a.cx + a.w / 2).min(b.cx + b.w / 2);
-    let y2 = (a.cy + a.h / 2).min(b.cy + b.h / 2);
+// Number of the other candidates suppressed together with the kept box (of its class, or any
+// class in agnostic mode, and of its group) overlapping it by at least `support_iou`, by default
+// `iou_threshold`. Genuine objects usually produce a cluster of candidates, the isolated
+// ones are often noise.
+fn support(kept: &BBox, candidates: &[BBox], options: &NmsOptions) -> usize {
+    let support_iou = options.support_iou.unwrap_or(options.iou_threshold);
+    candidates
+        .iter()
+        .filter(|c| c.row != kept.row && c.group == kept.group && (options.agnostic || c.class == kept.class))
+        .filter(|c| overlap(c, kept, options) >= support_iou)
+        .count()
+}
+
+// The boxes suppressed together, sorted for the NMS: the boxes of each class, or all of them
+// in agnostic mode, regardless of their class. Same for single-class outputs, skipping the
+// class grouping. With `sort_order: :asc` each group is reversed, the least probable box first.
+fn class_groups(bboxes: &[BBox], options: &NmsOptions) -> Vec<Vec<BBox>> {
+    let mut groups = sorted_class_groups(bboxes, options);
+    if options.sort_order == ProbOrder::Asc {
+        groups.iter_mut().for_each(|group| group.reverse());
+    }
+    groups
+}
+
+fn sorted_class_groups(bboxes: &[BBox], options: &NmsOptions) -> Vec<Vec<BBox>> {
+    let single_class = bboxes.iter().all(|b| b.class == bboxes[0].class);
+    let presorted = presorted(options);
+    if options.agnostic || single_class {
+        if presorted {
+            debug_assert!(is_sorted_by_prob(bboxes), "input_sorted: the boxes are not sorted by prob");
+            vec![bboxes.to_vec()]
+        } else {
+            vec![sorted_boxes(bboxes.to_vec(), &options.class_priorities, options.tie_prefer)]
+        }
+    } else {
+        // the `HashSet` order changes between the runs, the ascending ids give a stable output
+        let mut classes: Vec<u16> = get_classes(bboxes).into_iter().collect();
+        if options.sorted_classes {
+            classes.sort_unstable();
+        }
+        classes
+            .into_iter()
+            .map(|class| sorted_boxes_filtered_by_class(bboxes, class, presorted, options.tie_prefer))
+            .collect()
+    }
+}
+
+// Whether the hard NMS suppresses `bbox` with the more probable `kept` box of its group: with a
+// zero `iou_threshold` any kept box does, the most probable box is the only one kept, like the
+// zero threshold branch of `nms`.
+pub(crate) fn hard_suppresses(bbox: &BBox, kept: &BBox, options: &NmsOptions) -> bool {
+    options.iou_threshold <= 0.0 || suppresses(overlap(bbox, kept, options), options)
+}
+
+// Whether an overlap suppresses the less probable box: strictly above `iou_threshold`,
+// or also at the threshold with `threshold_inclusive`.
+fn suppresses(iou: f32, options: &NmsOptions) -> bool {
+    if options.threshold_inclusive {
+        iou >= options.iou_threshold
+    } else {
+        iou > options.iou_threshold
+    }
+}
+
+// Corners of a box in doubled coordinates, exact for the odd sizes.
+pub(crate) struct Corners {
+    x1: i64,
+    y1: i64,
+    x2: i64,
+    y2: i64,
+}
+
+impl Corners {
+    fn of(bbox: &BBox) -> Corners {
+        let (cx, cy, w, h) = (bbox.cx as i64, bbox.cy as i64, bbox.w as i64, bbox.h as i64);
+        Corners { x1: 2 * cx - w, y1: 2 * cy - h, x2: 2 * cx + w, y2: 2 * cy + h }
+    }
+
+    fn enclose(&mut self, other: &Corners) {
+        self.x1 = self.x1.min(other.x1);
+        self.y1 = self.y1.min(other.y1);
+        self.x2 = self.x2.max(other.x2);
+        self.y2 = self.y2.max(other.y2);
+    }
+
+    fn apply(&self, bbox: &mut BBox) {
+        bbox.cx = ((self.x1 + self.x2) / 4) as i32;
+        bbox.cy = ((self.y1 + self.y2) / 4) as i32;
+        bbox.w = ((self.x2 - self.x1) / 2) as i32;
+        bbox.h = ((self.y2 - self.y1) / 2) as i32;
+    }
+}
+
+// Global latency budget: keeps only the `budget` most probable candidates, whatever their class,
+// found with a partial selection instead of a sort. The order of the kept boxes is preserved.
+// Returns whether some candidates were dropped.
+fn truncate_to_budget(bboxes: &mut Vec<BBox>, budget: usize) -> bool {
+    if bboxes.len() <= budget {
+        return false;
+    }
+    if budget == 0 {
+        bboxes.clear();
+        return true;
+    }
+
+    let mut probs: Vec<f32> = bboxes.iter().map(|bbox| bbox.prob).collect();
+    let (_, &mut cutoff, _) = probs.select_nth_unstable_by(budget - 1, |a, b| b.total_cmp(a));
+    // how many boxes with the cutoff prob are kept
+    let mut ties = budget - probs.iter().filter(|&&prob| prob > cutoff).count();
+    bboxes.retain(|bbox| {
+        if bbox.prob > cutoff {
+            true
+        } else if bbox.prob == cutoff && ties > 0 {
+            ties -= 1;
+            true
+        } else {
+            false
+        }
+    });
+    true
+}
+
+// Cheap approximate pre-filter of the dense outputs: keeps only the most probable candidate of
+// each class (and group) in each `cell_size` square cell of the model coordinates, by the cell
+// of its center. The first one wins the ties, the order of the kept boxes is preserved.
+fn keep_cell_max(bboxes: &mut Vec<BBox>, cell_size: f32) {
+    let cell = |bbox: &BBox| {
+        let x = (bbox.cx as f32 / cell_size).floor() as i64;
+        let y = (bbox.cy as f32 / cell_size).floor() as i64;
+        (bbox.class, bbox.group, x, y)
+    };
+
+    let mut best: HashMap<(u16, u16, i64, i64), usize> = HashMap::new();
+    for (i, bbox) in bboxes.iter().enumerate() {
+        best.entry(cell(bbox))
+            .and_modify(|best_i| {
+                if bbox.prob > bboxes[*best_i].prob {
+                    *best_i = i;
+                }
+            })
+            .or_insert(i);
+    }
+
+    let mut i = 0;
+    bboxes.retain(|bbox| {
+        i += 1;
+        best[&cell(bbox)] == i - 1
+    });
+}
+
+// Latency safeguard: keeps only the `cap` most probable candidates of each class, bounding
+// the sort and the quadratic suppression of a class with thousands of candidates.
+// The order of the kept boxes is preserved. The n-th highest prob of each class is found
+// with a partial selection, without sorting all the candidates.
+fn cap_per_class(bboxes: &[BBox], cap: usize) -> Vec<BBox> {
+    let mut class_probs: HashMap<u16, Vec<f32>> = HashMap::new();
+    for bbox in bboxes {
+        class_probs.entry(bbox.class).or_default().push(bbox.prob);
+    }
+
+    // lowest prob kept for each capped class, and how many boxes with that prob are kept
+    let mut cutoffs: HashMap<u16, (f32, usize)> = HashMap::new();
+    for (class, mut probs) in class_probs {
+        if probs.len() <= cap {
+            continue;
+        }
+        if cap == 0 {
+            cutoffs.insert(class, (f32::INFINITY, 0));
+            continue;
+        }
+        let (_, &mut cutoff, _) = probs.select_nth_unstable_by(cap - 1, |a, b| b.total_cmp(a));
+        let above = probs.iter().filter(|&&prob| prob > cutoff).count();
+        cutoffs.insert(class, (cutoff, cap - above));
+    }
+
+    let mut capped_bboxes = Vec::new();
+    for bbox in bboxes {
+        let keep = match cutoffs.get_mut(&bbox.class) {
+            None => true,
+            Some((cutoff, _)) if bbox.prob > *cutoff => true,
+            Some((cutoff, ties)) if bbox.prob == *cutoff && *ties > 0 => {
+                *ties -= 1;
+                true
+            }
+            Some(_) => false,
+        };
+        if keep {
+            capped_bboxes.push(bbox.clone());
+        }
+    }
+    capped_bboxes
+}
+
+// Soft-NMS: the most probable box is kept and the `prob` of the others is decayed
+// by `exp(-iou² / sigma)`, over and over until no box is above `prob_threshold`.
+// `kept_offset` is the number of boxes already kept by `nms`, to index the suppressing boxes.
+fn soft_nms(
+    mut bboxes: Vec<BBox>,
+    options: &NmsOptions,
+    kept_offset: usize,
+    suppressed: &mut Vec<Suppressed>
+) -> Vec<BBox> {
+    let mut kept_boxes: Vec<BBox> = Vec::new();
+
+    while !bboxes.is_empty() {
+        let best_idx = bboxes
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.prob.total_cmp(&b.prob))
+            .map(|(i, _)| i)
+            .unwrap();
+        let best = bboxes.swap_remove(best_idx);
+
+        let by = kept_offset + kept_boxes.len();
+        bboxes.retain_mut(|bbox| {
+            let iou = overlap(&best, bbox, options);
+            bbox.prob *= (-(iou * iou) / class_sigma(bbox.class, options)).exp();
+            let kept = bbox.prob >= options.prob_threshold;
+            if !kept && options.suppressed {
+                suppressed.push(Suppressed { bbox: bbox.clone(), by, iou });
+            }
+            kept
+        });
+        kept_boxes.push(best);
+    }
+
+    kept_boxes
+}
+
+// Greedy NMS over the sorted boxes by the distance of the centers only: a box is dropped
+// when its center is within `radius` of the center of a more probable kept box.
+fn point_nms(bboxes: Vec<BBox>, options: &NmsOptions) -> Vec<BBox> {
+    let center_distance = |a: &BBox, b: &BBox| ((a.cx - b.cx) as f64).hypot((a.cy - b.cy) as f64) as f32;
+    // the overlap is 1.0 within the radius (inclusive), 0.0 beyond
+    let within_radius = |a: &BBox, b: &BBox| (center_distance(a, b) <= options.radius) as u8 as f32;
+    nms_with(&bboxes, 0.5, within_radius)
+}
+
+// Greedy NMS over the `boxes` sorted by `prob` descending with a caller-supplied overlap, like the
+// DIoU or the distance of the centers, the entry point of the Rust API: a box is suppressed when
+// its overlap with a kept box is above `threshold`. Returns the kept boxes, in order. The NIF
+// methods are built on it, point NMS directly and hard NMS on its verdicts.
+pub fn nms_with<F: Fn(&BBox, &BBox) -> f32>(boxes: &[BBox], threshold: f32, overlap_fn: F) -> Vec<BBox> {
+    greedy_verdicts(boxes, KeptRefs::new(|overlap| overlap > threshold, overlap_fn))
+        .into_iter()
+        .zip(boxes)
+        .filter(|(verdict, _)| verdict.is_none())
+        .map(|(_, bbox)| bbox.clone())
+        .collect()
+}
+
+// The boxes kept so far by `greedy_verdicts`, stored for their overlap: the boxes themselves with
+// a caller-supplied overlap, or the struct of arrays of `soa` for the IoU of the hard NMS.
+pub(crate) trait KeptBoxes<'b> {
+    // The highest overlap is the greatest of them.
+    type Overlap: Copy + PartialOrd;
+
+    fn push(&mut self, bbox: &'b BBox);
+    // The overlaps of `bbox` with each kept box, in the order they were kept, written in `overlaps`.
+    fn overlaps(&self, bbox: &BBox, overlaps: &mut Vec<Self::Overlap>);
+    fn suppresses(&self, overlap: Self::Overlap) -> bool;
+    // The overlap as given in the verdicts.
+    fn value(&self, overlap: Self::Overlap) -> f32;
+}
+
+// The kept boxes of a caller-supplied overlap, with their suppression predicate.
+struct KeptRefs<'b, S, F> {
+    bboxes: Vec<&'b BBox>,
+    suppresses: S,
+    overlap_fn: F,
+}
+
+impl<S, F> KeptRefs<'_, S, F>
+where
+    S: Fn(f32) -> bool,
+    F: Fn(&BBox, &BBox) -> f32,
+{
+    fn new(suppresses: S, overlap_fn: F) -> Self {
+        KeptRefs { bboxes: Vec::new(), suppresses, overlap_fn }
+    }
+}
+
+impl<'b, S, F> KeptBoxes<'b> for KeptRefs<'b, S, F>
+where
+    S: Fn(f32) -> bool,
+    F: Fn(&BBox, &BBox) -> f32,
+{
+    type Overlap = f32;
+
+    fn push(&mut self, bbox: &'b BBox) {
+        self.bboxes.push(bbox);
+    }
+
+    fn overlaps(&self, bbox: &BBox, overlaps: &mut Vec<f32>) {
+        overlaps.clear();
+        overlaps.extend(self.bboxes.iter().map(|kb| (self.overlap_fn)(bbox, kb)));
+    }
+
+    fn suppresses(&self, overlap: f32) -> bool {
+        (self.suppresses)(overlap)
+    }
+
+    fn value(&self, overlap: f32) -> f32 {
+        overlap
+    }
+}
+
+// The greedy loop of `nms_with`, with the verdict of each box: a box is suppressed when its
+// highest overlap with the `kept_boxes` so far suppresses it. For each box, `None` when kept,
+// or the index (in the kept boxes) of the kept box overlapping it the most and their overlap.
+fn greedy_verdicts<'b, K: KeptBoxes<'b>>(bboxes: &'b [BBox], mut kept_boxes: K) -> Vec<Option<(usize, f32)>> {
+    let mut overlaps = Vec::new();
 
-    // Calculate the area of intersection
-    let intersection_area = (x2 - x1).max(0) * (y2 - y1).max(0);
+    bboxes
+        .iter()
+        .map(|bbox| {
+            kept_boxes.overlaps(bbox, &mut overlaps);
+            // the first of the kept boxes with the highest overlap
+            let highest = overlaps
+                .iter()
+                .copied()
+                .enumerate()
+                .fold(None, |highest: Option<(usize, K::Overlap)>, (i, overlap)| match highest {
+                    Some((_, highest_overlap)) if highest_overlap >= overlap => highest,
+                    _ => Some((i, overlap)),
+                });
+            match highest {
+                Some((i, overlap)) if kept_boxes.suppresses(overlap) => Some((i, kept_boxes.value(overlap))),
+                _ => {
+                    kept_boxes.push(bbox);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+// Matrix NMS (SOLOv2): all the decays are computed at once from the IoU matrix of the
+// sorted boxes. A box is decayed by its IoU with each more probable box, compensated by
+// how much that box was itself overlapped: `min_i exp(-(iou_ij² - max_iou_i²) / sigma)`.
+fn matrix_nms(bboxes: Vec<BBox>, options: &NmsOptions) -> Vec<BBox> {
+    let decays = matrix_decays(
+        &bboxes,
+        |a, b| overlap(a, b, options),
+        |bbox| class_sigma(bbox.class, options),
+        cfg!(feature = "parallel")
+    );
+
+    bboxes
+        .into_iter()
+        .zip(decays)
+        .filter_map(|(mut bbox, decay)| {
+            bbox.prob *= decay;
+            (bbox.prob >= options.prob_threshold).then_some(bbox)
+        })
+        .collect()
+}
 
-    // Calculate the area of both bounding boxes
-    let a_area = a.w * a.h;
-    let b_area = b.w * b.h;
+// The Matrix NMS decay of each of the sorted `bboxes`, on the rayon threads when `parallel`.
+fn matrix_decays<F, S>(bboxes: &[BBox], overlap_fn: F, sigma_fn: S, parallel: bool) -> Vec<f32>
+where
+    F: Fn(&BBox, &BBox) -> f32 + Sync + Send,
+    S: Fn(&BBox) -> f32 + Sync + Send,
+{
+    // triangular IoU matrix, the IoUs of each box with the more probable ones
+    let ious: Vec<Vec<f32>> = map_indexes(bboxes.len(), parallel, |j| {
+        (0..j).map(|i| overlap_fn(&bboxes[i], &bboxes[j])).collect()
+    });
+    // highest IoU of each box with a more probable one
+    let max_ious: Vec<f32> = ious.iter().map(|row| row.iter().copied().fold(0.0_f32, f32::max)).collect();
 
-    // Calculate the area of union
-    let union_area = a_area + b_area - intersection_area;
+    map_indexes(bboxes.len(), parallel, |j| {
+        let sigma = sigma_fn(&bboxes[j]);
+        ious[j]
+            .iter()
+            .zip(&max_ious)
+            .map(|(iou, max_iou)| (-(iou.powi(2) - max_iou.powi(2)) / sigma).exp())
+            .fold(1.0_f32, f32::min)
+    })
+}
 
-    // Calculate and return the IoU
-    if union_area == 0 {
-        0.0
+// `(0..n).map(f)`, spread over the rayon threads when `parallel` with the `parallel` feature.
+// Each item is computed on its own, so the results don't depend on the number of threads.
+#[cfg(feature = "parallel")]
+fn map_indexes<T: Send>(n: usize, parallel: bool, f: impl Fn(usize) -> T + Sync + Send) -> Vec<T> {
+    use rayon::prelude::*;
+    if parallel {
+        (0..n).into_par_iter().map(f).collect()
     } else {
-        intersection_area as f32 / union_area as f32
+        (0..n).map(f).collect()
+    }
+}
+
+#[cfg(not(feature = "parallel"))]
+fn map_indexes<T>(n: usize, _parallel: bool, f: impl Fn(usize) -> T) -> Vec<T> {
+    (0..n).map(f).collect()
+}
+
+// `groups.iter().map(f)`, with the `threads` feature the groups are split in contiguous chunks
+// across `threads` scoped threads (without rayon). Each group is computed on its own, so the
+// results are the same of the sequential path.
+#[cfg(feature = "threads")]
+fn map_groups<T: Send>(groups: &[Vec<BBox>], threads: usize, f: impl Fn(&[BBox]) -> T + Sync) -> Vec<T> {
+    let threads = threads.clamp(1, groups.len().max(1));
+    if threads == 1 {
+        return groups.iter().map(|group| f(group)).collect();
     }
+
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = groups
+            .chunks(groups.len().div_ceil(threads))
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|group| f(group)).collect::<Vec<T>>()))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect()
+    })
 }
 
-fn sorted_boxes_filtered_by_class(bboxes: &Vec<BBox>, class: u16) -> Vec<BBox> {
+#[cfg(not(feature = "threads"))]
+fn map_groups<T>(groups: &[Vec<BBox>], _threads: usize, f: impl Fn(&[BBox]) -> T) -> Vec<T> {
+    groups.iter().map(|group| f(group)).collect()
+}
+
+// Gaussian decay parameter of the boxes of `class`, `sigma` for the classes not in `class_sigmas`.
+fn class_sigma(class: u16, options: &NmsOptions) -> f32 {
+    options
+        .class_sigmas
+        .as_ref()
+        .and_then(|sigmas| sigmas.get(&class).copied())
+        .unwrap_or(options.sigma)
+}
+
+// Adds the most probable rejected boxes until there are `min_results` kept boxes.
+// Like in `nms`, a rejected box suppressed by a kept box of the same class and group is skipped.
+fn backfill(kept_bboxes: &mut Vec<BBox>, rejected_bboxes: Vec<BBox>, options: &NmsOptions) {
+    if kept_bboxes.len() >= options.min_results {
+        return;
+    }
+
+    for bbox in sorted_boxes(rejected_bboxes, &options.class_priorities, options.tie_prefer) {
+        if kept_bboxes.len() >= options.min_results {
+            break;
+        }
+        let overlaps = kept_bboxes.iter().any(|kb| {
+            (options.agnostic || kb.class == bbox.class) && kb.group == bbox.group && hard_suppresses(&bbox, kb, options)
+        });
+        if !overlaps {
+            kept_bboxes.push(bbox);
+        }
+    }
+}
+
+// Same IoU used by `nms`, so evaluation tooling matches the post-processing.
+// Boxes with 5 elements are oriented boxes `[cx, cy, w, h, angle]`.
+#[rustler::nif(name = "iou_nif")]
+fn iou(a: Vec<f32>, b: Vec<f32>, options: NmsOptions) -> NifResult<f64> {
+    match (a.len(), b.len()) {
+        (4, 4) => {
+            let (Some(a), Some(b)) = (bbox_from_row(&a, &[]), bbox_from_row(&b, &[])) else {
+                return Err(Error::BadArg);
+            };
+            Ok(match options.precision {
+                Precision::F32 => calc_iou(&a, &b, &options) as f64,
+                Precision::F64 => calc_iou_f64(&a, &b, &options),
+                Precision::Fixed => calc_iou_fixed(&a, &b, &options) as f64,
+            })
+        }
+        (5, 5) => {
+            let (Some(a), Some(b)) = (oriented_bbox(&a), oriented_bbox(&b)) else {
+                return Err(Error::BadArg);
+            };
+            Ok(rotated_iou(&a, &b, options.overlap_metric) as f64)
+        }
+        _ => Err(Error::BadArg),
+    }
+}
+
+fn oriented_bbox(coords: &[f32]) -> Option<BBox> {
+    let mut bbox = bbox_from_row(&coords[..4], &[])?;
+    bbox.angle = coords[4];
+    Some(bbox)
+}
+
+// The overlap measure used to suppress boxes.
+fn overlap(a: &BBox, b: &BBox, options: &NmsOptions) -> f32 {
+    if options.obb {
+        rotated_iou(a, b, options.overlap_metric)
+    } else {
+        match options.precision {
+            Precision::F32 => calc_iou(a, b, options),
+            // rounded once, instead of rounding both areas and the division
+            Precision::F64 => calc_iou_f64(a, b, options) as f32,
+            Precision::Fixed => calc_iou_fixed(a, b, options),
+        }
+    }
+}
+
+fn calc_iou(a: &BBox, b: &BBox, options: &NmsOptions) -> f32 {
+    let (intersection_area, denominator) = iou_areas(a, b, options);
+    f32_overlap(intersection_area, denominator, a, b, options)
+}
+
+// The f32 overlap of the areas of `iou_areas`, also used by the struct of arrays of `soa`.
+pub(crate) fn f32_overlap(intersection_area: i64, denominator: i64, a: &BBox, b: &BBox, options: &NmsOptions) -> f32 {
+    if denominator == 0 {
+        zero_area_iou(a, b, options) as f32
+    } else {
+        intersection_area as f32 / (denominator as f32 + options.iou_eps)
+    }
+}
+
+// Above 2^24 pixels the f32 areas are rounded, large boxes need the f64 division.
+fn calc_iou_f64(a: &BBox, b: &BBox, options: &NmsOptions) -> f64 {
+    let (intersection_area, denominator) = iou_areas(a, b, options);
+
+    if denominator == 0 {
+        zero_area_iou(a, b, options)
+    } else {
+        intersection_area as f64 / (denominator as f64 + options.iou_eps as f64)
+    }
+}
+
+// Steps of the fixed-point IoU, the IoU is a multiple of 1 / IOU_FIXED_STEPS.
+pub(crate) const IOU_FIXED_STEPS: i64 = 1024;
+
+// The IoU of the integer areas divided in integer arithmetic, in fixed point: truncated to a
+// multiple of 1 / IOU_FIXED_STEPS, so by less than 0.001. `iou_eps` is not applied.
+fn calc_iou_fixed(a: &BBox, b: &BBox, options: &NmsOptions) -> f32 {
+    let (intersection_area, denominator) = iou_areas(a, b, options);
+
+    if denominator == 0 {
+        zero_area_iou(a, b, options) as f32
+    } else {
+        (intersection_area * IOU_FIXED_STEPS / denominator) as f32 / IOU_FIXED_STEPS as f32
+    }
+}
+
+// IoU of two zero-area boxes: 0, or 1 for the coincident boxes (like the point detections
+// or the tiny boxes collapsed by the clamping) with `zero_union_iou: :one`, so the duplicates
+// are suppressed.
+fn zero_area_iou(a: &BBox, b: &BBox, options: &NmsOptions) -> f64 {
+    let coincident = (a.cx, a.cy, a.w, a.h) == (b.cx, b.cy, b.w, b.h);
+    if options.zero_union_iou == ZeroUnionIou::One && coincident { 1.0 } else { 0.0 }
+}
+
+// Intersection area and the denominator of the `overlap_metric` (the union for the IoU),
+// in i64 to not overflow with large boxes.
+// With `pixel_inclusive` the edges are pixels and counted in the sizes: a box
+// from x1 to x2 is `x2 - x1 + 1` pixels wide.
+fn iou_areas(a: &BBox, b: &BBox, options: &NmsOptions) -> (i64, i64) {
+    let edge = options.pixel_inclusive as i64;
+    let intersection_area = intersection_area(corners(a), corners(b), edge);
+    let denominator = overlap_denominator(intersection_area, box_area(a, edge), box_area(b, edge), options);
+    (intersection_area, denominator)
+}
+
+// The `(x1, y1, x2, y2)` corners of the box, in i64 for the areas.
+pub(crate) fn corners(b: &BBox) -> (i64, i64, i64, i64) {
+    (
+        (b.cx - b.w / 2) as i64,
+        (b.cy - b.h / 2) as i64,
+        (b.cx + b.w / 2) as i64,
+        (b.cy + b.h / 2) as i64,
+    )
+}
+
+pub(crate) fn box_area(b: &BBox, edge: i64) -> i64 {
+    (b.w as i64 + edge) * (b.h as i64 + edge)
+}
+
+pub(crate) fn intersection_area(a: (i64, i64, i64, i64), b: (i64, i64, i64, i64), edge: i64) -> i64 {
+    let (x1, y1) = (a.0.max(b.0), a.1.max(b.1));
+    let (x2, y2) = (a.2.min(b.2), a.3.min(b.3));
+    (x2 - x1 + edge).max(0) * (y2 - y1 + edge).max(0)
+}
+
+// The union for the IoU, or the smaller or the larger area.
+pub(crate) fn overlap_denominator(intersection_area: i64, a_area: i64, b_area: i64, options: &NmsOptions) -> i64 {
+    match options.overlap_metric {
+        OverlapMetric::Iou => a_area + b_area - intersection_area,
+        OverlapMetric::Iomin => a_area.min(b_area),
+        OverlapMetric::Iomax => a_area.max(b_area),
+    }
+}
+
+// With `presorted` the filtering keeps the relative order of the boxes, already sorted.
+fn sorted_boxes_filtered_by_class(bboxes: &[BBox], class: u16, presorted: bool, tie_prefer: TiePrefer) -> Vec<BBox> {
     let class_bboxes: Vec<BBox> = bboxes.iter().filter(|b| b.class == class).cloned().collect();
+    if presorted {
+        debug_assert!(is_sorted_by_prob(&class_bboxes), "input_sorted: the boxes are not sorted by prob");
+        return class_bboxes;
+    }
     let mut sorted_bboxes = class_bboxes.clone();
-    sorted_bboxes.sort_by(|a, b| b.prob.partial_cmp(&a.prob).unwrap());
+    sorted_bboxes.sort_by(|a, b| b.prob.total_cmp(&a.prob).then_with(|| tie_prefer.compare(a, b)));
     sorted_bboxes
 }
 
-fn get_classes(boxes: &Vec<BBox>) -> HashSet<u16> {
+// Sorts by `prob` weighted by the class priority (1.0 when not given), so a
+// higher-priority class comes first even with a slightly lower `prob`.
+// The weight is only used for ordering, the returned `prob` is untouched.
+fn sorted_boxes(
+    mut bboxes: Vec<BBox>,
+    class_priorities: &Option<HashMap<u16, f32>>,
+    tie_prefer: TiePrefer
+) -> Vec<BBox> {
+    let sort_key = |b: &BBox| {
+        let priority = class_priorities
+            .as_ref()
+            .and_then(|priorities| priorities.get(&b.class))
+            .copied()
+            .unwrap_or(1.0);
+        b.prob * priority
+    };
+    bboxes.sort_by(|a, b| sort_key(b).total_cmp(&sort_key(a)).then_with(|| tie_prefer.compare(a, b)));
+    bboxes
+}
+
+fn is_sorted_by_prob(bboxes: &[BBox]) -> bool {
+    bboxes.is_sorted_by(|a, b| a.prob >= b.prob)
+}
+
+// The `input_sorted` hint: the candidates are in the order of the model output, already
+// sorted by `prob` descending. The exact `dedup` and `multi_label` reorder them and the
+// class priorities and `tie_prefer` change the order, so the boxes are sorted anyway with them.
+fn presorted(options: &NmsOptions) -> bool {
+    options.input_sorted
+        && options.tie_prefer == TiePrefer::First
+        && !options.multi_label
+        && !(options.dedup && options.dedup_tolerance() <= 0.0)
+        && options.class_priorities.is_none()
+}
+
+fn get_classes(boxes: &[BBox]) -> HashSet<u16> {
     let mut classes = HashSet::new();
     for b in boxes {
         classes.insert(b.class);
@@ -176,20 +2046,55 @@ fn get_classes(boxes: &Vec<BBox>) -> HashSet<u16> {
     classes
 }
 
+rustler::init!("Elixir.YoloFastNMS");
 
-fn transpose_matrix(matrix: &Vec<Vec<f32>>) -> Vec<Vec<f32>> {
-    let rows = matrix.len();
-    let cols = if rows > 0 { matrix[0].len() } else { 0 };
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let mut transposed = vec![vec![0.0; rows]; cols];
+    #[test]
+    fn nms_with_a_custom_overlap() {
+        let boxes = [
+            BBox::new(100, 100, 40, 40, 0.9, 0),
+            BBox::new(110, 100, 40, 40, 0.8, 0),
+            BBox::new(300, 100, 40, 40, 0.7, 0),
+            BBox::new(330, 100, 40, 40, 0.6, 0),
+        ];
+        // suppresses the boxes with the centers closer than 20 pixels on the x axis
+        let close = |a: &BBox, b: &BBox| 1.0 - ((a.cx - b.cx).abs() as f32 / 20.0).min(1.0);
 
-    for i in 0..rows {
-        for j in 0..cols {
-            transposed[j][i] = matrix[i][j];
-        }
+        let kept = nms_with(&boxes, 0.0, close);
+        assert_eq!(vec![0.9, 0.7, 0.6], kept.iter().map(|b| b.prob).collect::<Vec<f32>>());
+
+        // no box is suppressed above the highest overlap
+        assert_eq!(4, nms_with(&boxes, 1.0, close).len());
     }
 
-    transposed
-}
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_matrix_decays_match_the_scalar_ones() {
+        // dense frame of 5000 boxes packed in a 240x240 region, with a fixed LCG
+        let mut seed: u32 = 42;
+        let mut next = |max: u32| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) % max
+        };
+        let mut boxes: Vec<BBox> = (0..5000)
+            .map(|_| {
+                let (cx, cy) = (200 + next(240) as i32, 200 + next(240) as i32);
+                let (w, h) = (30 + next(50) as i32, 30 + next(50) as i32);
+                BBox::new(cx, cy, w, h, next(1000) as f32 / 1000.0, 0)
+            })
+            .collect();
+        boxes.sort_by(|a, b| b.prob.total_cmp(&a.prob));
 
-rustler::init!("Elixir.YoloFastNMS");
\ No newline at end of file
+        let iou = |a: &BBox, b: &BBox| {
+            let (intersection, denominator) =
+                (intersection_area(corners(a), corners(b), 0), box_area(a, 0) + box_area(b, 0));
+            intersection as f32 / (denominator - intersection) as f32
+        };
+        let scalar = matrix_decays(&boxes, iou, |_| 0.5, false);
+        assert_eq!(scalar, matrix_decays(&boxes, iou, |_| 0.5, true));
+        assert!(scalar.iter().any(|&decay| decay < 1.0));
+    }
+}
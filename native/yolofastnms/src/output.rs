@@ -0,0 +1,566 @@
+use rustler::{Encoder, Env, NifResult, OwnedBinary, Term};
+
+use std::collections::HashMap;
+
+use crate::{BBox, Endianness, NmsOptions, OutputFormat, ParseMode, SortOrder, Suppressed, Trace};
+
+mod atoms {
+    rustler::atoms! {
+        cx,
+        cy,
+        w,
+        h,
+        prob,
+        class_idx,
+        class_probs,
+        class_scores,
+        color_index,
+        top_classes,
+        angle,
+        row_index,
+        clamped,
+        display,
+        label,
+        class_counts,
+        suppressed,
+        suppressed_by,
+        iou,
+        data,
+        count,
+        columns,
+        class_mapping,
+        fingerprint,
+        transpose,
+        track_id,
+        coverage_area,
+        coverage_fraction,
+        truncated,
+        x1,
+        y1,
+        x2,
+        y2,
+        group,
+        dropped_rows,
+        duplicate_rows,
+        class_centroids,
+    }
+}
+
+// The detections, or `{detections, metadata}` with the `metadata` option.
+pub(crate) fn encode_bboxes<'a>(
+    env: Env<'a>,
+    bboxes: Vec<BBox>,
+    trace: Trace,
+    options: &NmsOptions
+) -> NifResult<Term<'a>> {
+    if options.parse_mode == ParseMode::Strict && trace.dropped > 0 {
+        return Err(rustler::Error::RaiseTerm(Box::new(format!(
+            "parse_mode: :strict, {} malformed rows (NaN, infinite or out of range coordinates, negative size or invalid id)",
+            trace.dropped
+        ))));
+    }
+    let bboxes = match options.min_center_distance {
+        Some(min_distance) => space_out(bboxes, min_distance),
+        None => bboxes,
+    };
+    let bboxes = match options.top_n {
+        Some(n) => top_n(bboxes, n),
+        None => bboxes,
+    };
+    let bboxes = match options.sort {
+        SortOrder::Raster => raster_order(bboxes),
+        SortOrder::Nms => bboxes,
+    };
+
+    let bboxes: Vec<BBox> = match (options.clamp, options.image_size) {
+        (true, Some(image_size)) => bboxes.into_iter().map(|bbox| clamp_to_image(bbox, image_size)).collect(),
+        _ => bboxes,
+    };
+
+    // the kept classes numbered contiguously, in the `keep_classes` order
+    let (bboxes, trace) = match (options.relabel, &options.keep_classes) {
+        (true, Some(keep_classes)) => (
+            bboxes.into_iter().map(|bbox| relabel(bbox, keep_classes)).collect(),
+            Trace {
+                suppressed: trace
+                    .suppressed
+                    .into_iter()
+                    .map(|s| Suppressed { bbox: relabel(s.bbox, keep_classes), ..s })
+                    .collect(),
+                ..trace
+            },
+        ),
+        _ => (bboxes, trace),
+    };
+
+    if !options.metadata {
+        return encode_groups(env, bboxes, options);
+    }
+
+    let metadata = encode_metadata(env, &bboxes, trace, options)?;
+    Ok((encode_groups(env, bboxes, options)?, metadata).encode(env))
+}
+
+// Final spacing constraint across the classes: the kept boxes with a center closer than
+// `min_distance` to a more probable one are dropped, in the model coordinates. The order
+// of the remaining boxes is preserved.
+fn space_out(bboxes: Vec<BBox>, min_distance: f32) -> Vec<BBox> {
+    let mut by_prob: Vec<usize> = (0..bboxes.len()).collect();
+    by_prob.sort_by(|&a, &b| bboxes[b].prob.total_cmp(&bboxes[a].prob));
+
+    let too_close = |a: &BBox, b: &BBox| {
+        (((a.cx - b.cx) as f64).hypot((a.cy - b.cy) as f64) as f32) < min_distance
+    };
+    let mut kept: Vec<usize> = Vec::new();
+    for i in by_prob {
+        if !kept.iter().any(|&k| too_close(&bboxes[k], &bboxes[i])) {
+            kept.push(i);
+        }
+    }
+
+    let mut keep = vec![false; bboxes.len()];
+    for i in kept {
+        keep[i] = true;
+    }
+    bboxes.into_iter().zip(keep).filter_map(|(bbox, keep)| keep.then_some(bbox)).collect()
+}
+
+// The `n` most probable boxes, all of them for 0, sorted by `prob` descending.
+// The sort is stable, the boxes with the same `prob` keep the NMS order.
+fn top_n(mut bboxes: Vec<BBox>, n: usize) -> Vec<BBox> {
+    bboxes.sort_by(|a, b| b.prob.total_cmp(&a.prob));
+    if n > 0 {
+        bboxes.truncate(n);
+    }
+    bboxes
+}
+
+// Sorted by `cy` then `cx` in the model coordinates, the ties by `prob` descending then class,
+// an order of the kept boxes that doesn't depend on the order of the candidates.
+fn raster_order(mut bboxes: Vec<BBox>) -> Vec<BBox> {
+    bboxes.sort_by(|a, b| {
+        (a.cy, a.cx)
+            .cmp(&(b.cy, b.cx))
+            .then_with(|| b.prob.total_cmp(&a.prob))
+            .then_with(|| a.class.cmp(&b.class))
+    });
+    bboxes
+}
+
+// %{class_counts: %{class_idx => kept boxes}}, plus the `class_mapping` with `relabel`,
+// the `fingerprint` and the `suppressed` boxes with their options
+fn encode_metadata<'a>(
+    env: Env<'a>,
+    bboxes: &[BBox],
+    trace: Trace,
+    options: &NmsOptions
+) -> NifResult<Term<'a>> {
+    let Trace { suppressed, truncated, dropped, duplicate_rows } = trace;
+    let mut class_counts: HashMap<u16, usize> = HashMap::new();
+    for bbox in bboxes {
+        *class_counts.entry(bbox.class).or_insert(0) += 1;
+    }
+
+    let metadata = Term::map_new(env)
+        .map_put(atoms::class_counts(), class_counts)?
+        .map_put(atoms::dropped_rows(), dropped)?;
+    let metadata = match (options.relabel, &options.keep_classes) {
+        (true, Some(keep_classes)) => {
+            // %{class_idx => original class_idx}
+            let class_mapping: HashMap<usize, u16> = keep_classes.iter().copied().enumerate().collect();
+            metadata.map_put(atoms::class_mapping(), class_mapping)?
+        }
+        _ => metadata,
+    };
+    let metadata = if options.fingerprint {
+        metadata.map_put(atoms::fingerprint(), fingerprint(bboxes, options))?
+    } else {
+        metadata
+    };
+    let metadata = if options.coverage {
+        let area = coverage_area(bboxes, options);
+        let metadata = metadata.map_put(atoms::coverage_area(), area)?;
+        match options.image_size {
+            Some((width, height)) if width > 0 && height > 0 => {
+                // the image mapped to the output coordinates like the boxes
+                let gain = options.letterbox.map_or(1.0, |(gain, _, _)| gain as f64);
+                let image_area = width as f64 * height as f64 * (options.scale as f64 / gain).powi(2);
+                metadata.map_put(atoms::coverage_fraction(), area / image_area)?
+            }
+            _ => metadata,
+        }
+    } else {
+        metadata
+    };
+    let metadata = if options.duplicate_rows {
+        metadata.map_put(atoms::duplicate_rows(), duplicate_rows)?
+    } else {
+        metadata
+    };
+    let metadata = if options.class_centroids {
+        metadata.map_put(atoms::class_centroids(), class_centroids(bboxes, options))?
+    } else {
+        metadata
+    };
+    let metadata = if options.candidate_budget.is_some() {
+        metadata.map_put(atoms::truncated(), truncated)?
+    } else {
+        metadata
+    };
+    // the orientation chosen by `auto_transpose`
+    let metadata = if options.auto_transpose {
+        metadata.map_put(atoms::transpose(), options.transpose)?
+    } else {
+        metadata
+    };
+    if !options.suppressed {
+        return Ok(metadata);
+    }
+
+    let suppressed = suppressed
+        .into_iter()
+        .map(|Suppressed { bbox, by, iou }| {
+            bbox_to_map(env, bbox, options)?
+                .map_put(atoms::suppressed_by(), by)?
+                .map_put(atoms::iou(), iou)
+        })
+        .collect::<NifResult<Vec<Term<'a>>>>()?;
+    metadata.map_put(atoms::suppressed(), suppressed)
+}
+
+fn encode_groups<'a>(
+    env: Env<'a>,
+    bboxes: Vec<BBox>,
+    options: &NmsOptions
+) -> NifResult<Term<'a>> {
+    if !options.group_by_class {
+        return encode_detections(env, bboxes, options);
+    }
+
+    // `nms` returns the boxes already grouped by class, here we just split them
+    let mut groups: Vec<(u16, Vec<BBox>)> = Vec::new();
+    for bbox in bboxes {
+        match groups.iter_mut().find(|(class, _)| *class == bbox.class) {
+            Some((_, group)) => group.push(bbox),
+            None => groups.push((bbox.class, vec![bbox])),
+        }
+    }
+
+    let mut map = Term::map_new(env);
+    for (class, group) in groups {
+        map = map.map_put(class, encode_detections(env, group, options)?)?;
+    }
+    Ok(map)
+}
+
+fn encode_detections<'a>(
+    env: Env<'a>,
+    bboxes: Vec<BBox>,
+    options: &NmsOptions
+) -> NifResult<Term<'a>> {
+    match options.format {
+        OutputFormat::List => Ok(bboxes_to_result(bboxes, options).encode(env)),
+        OutputFormat::Boxes => Ok(bboxes_to_boxes(bboxes, options).encode(env)),
+        OutputFormat::Binary => bboxes_to_binary(env, bboxes, options),
+        OutputFormat::Flat => bboxes_to_flat(env, bboxes, options),
+        OutputFormat::YoloLabel => Ok(bboxes_to_labels(env, bboxes, options).encode(env)),
+        OutputFormat::Map => {
+            let maps = bboxes
+                .into_iter()
+                .map(|bbox| bbox_to_map(env, bbox, options))
+                .collect::<NifResult<Vec<Term<'a>>>>()?;
+            Ok(maps.encode(env))
+        }
+    }
+}
+
+// %{class_idx => {cx, cy}} of the centers of the boxes of each class weighted by their `prob`, in the
+// output coordinates of the returned boxes. The classes whose boxes all have a zero `prob` take the
+// plain mean of the centers.
+fn class_centroids(bboxes: &[BBox], options: &NmsOptions) -> HashMap<u16, (f64, f64)> {
+    // class => (sum of prob * cx, sum of prob * cy, sum of prob, sum of cx, sum of cy, count)
+    let mut sums: HashMap<u16, [f64; 6]> = HashMap::new();
+    for bbox in bboxes {
+        let [cx, cy, _w, _h] = coordinates(bbox, options).map(|c| c as f64);
+        let prob = bbox.prob as f64;
+        let sum = sums.entry(bbox.class).or_insert([0.0; 6]);
+        sum[0] += prob * cx;
+        sum[1] += prob * cy;
+        sum[2] += prob;
+        sum[3] += cx;
+        sum[4] += cy;
+        sum[5] += 1.0;
+    }
+    sums.into_iter()
+        .map(|(class, [wx, wy, weight, x, y, count])| {
+            let centroid = if weight > 0.0 { (wx / weight, wy / weight) } else { (x / count, y / count) };
+            (class, centroid)
+        })
+        .collect()
+}
+
+// Area of the union of the boxes, in the output coordinates of the returned boxes, without
+// counting the overlaps twice. Sweep over the x edges: in each slab between two consecutive edges
+// the y intervals of the boxes crossing it are merged.
+fn coverage_area(bboxes: &[BBox], options: &NmsOptions) -> f64 {
+    let corners: Vec<[f64; 4]> = bboxes
+        .iter()
+        .map(|bbox| {
+            let [cx, cy, w, h] = coordinates(bbox, options).map(|c| c as f64);
+            [cx - w / 2.0, cy - h / 2.0, cx + w / 2.0, cy + h / 2.0]
+        })
+        .collect();
+    let mut xs: Vec<f64> = corners.iter().flat_map(|&[x1, _, x2, _]| [x1, x2]).collect();
+    xs.sort_unstable_by(f64::total_cmp);
+    xs.dedup();
+
+    let mut area = 0.0;
+    let mut intervals: Vec<(f64, f64)> = Vec::new();
+    for slab in xs.windows(2) {
+        let (x1, x2) = (slab[0], slab[1]);
+        intervals.clear();
+        intervals.extend(corners.iter().filter(|c| c[0] <= x1 && c[2] >= x2).map(|c| (c[1], c[3])));
+        intervals.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+
+        let mut covered = 0.0;
+        let mut current: Option<(f64, f64)> = None;
+        for &(y1, y2) in &intervals {
+            current = match current {
+                Some((start, end)) if y1 <= end => Some((start, end.max(y2))),
+                Some((start, end)) => {
+                    covered += end - start;
+                    Some((y1, y2))
+                }
+                None => Some((y1, y2)),
+            };
+        }
+        if let Some((start, end)) = current {
+            covered += end - start;
+        }
+        area += covered * (x2 - x1);
+    }
+    area
+}
+
+// The probabilities and the angles are hashed in steps of `FINGERPRINT_STEP`.
+const FINGERPRINT_STEP: f32 = 0.001;
+
+// Stable 64-bit FNV-1a hash of the detections, independent of their order: the boxes are
+// hashed sorted, with the integer model coordinates and the rounded `prob` and angle, so the same
+// boxes give the same fingerprint across runs and releases. With a `match_tolerance` the
+// coordinates are rounded to its multiples, so most sub-tolerance moves keep the fingerprint.
+fn fingerprint(bboxes: &[BBox], options: &NmsOptions) -> u64 {
+    let coordinate = |c: i32| match options.match_tolerance {
+        Some(tolerance) if tolerance > 0.0 => (c as f32 / tolerance).round() as i64,
+        _ => c as i64,
+    };
+    let mut keys: Vec<[i64; 7]> = bboxes
+        .iter()
+        .map(|bbox| {
+            let prob = (bbox.prob / FINGERPRINT_STEP).round() as i64;
+            let angle = (bbox.angle / FINGERPRINT_STEP).round() as i64;
+            let [cx, cy, w, h] = [bbox.cx, bbox.cy, bbox.w, bbox.h].map(coordinate);
+            [bbox.class as i64, cx, cy, w, h, angle, prob]
+        })
+        .collect();
+    keys.sort_unstable();
+
+    keys.iter().flatten().flat_map(|value| value.to_le_bytes()).fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Replaces the class of the box with its index in `keep_classes`.
+fn relabel(mut bbox: BBox, keep_classes: &[u16]) -> BBox {
+    if let Some(class) = keep_classes.iter().position(|&class| class == bbox.class) {
+        bbox.class = class as u16;
+    }
+    bbox
+}
+
+// Moves the edges of the box inside the `{width, height}` image, flagging it as clamped when changed.
+fn clamp_to_image(mut bbox: BBox, (width, height): (i32, i32)) -> BBox {
+    let (x1, x2) = (bbox.cx - bbox.w / 2, bbox.cx + bbox.w / 2);
+    let (y1, y2) = (bbox.cy - bbox.h / 2, bbox.cy + bbox.h / 2);
+    let (clamped_x1, clamped_x2) = (x1.clamp(0, width), x2.clamp(0, width));
+    let (clamped_y1, clamped_y2) = (y1.clamp(0, height), y2.clamp(0, height));
+
+    if (clamped_x1, clamped_x2) != (x1, x2) {
+        bbox.w = clamped_x2 - clamped_x1;
+        bbox.cx = clamped_x1 + bbox.w / 2;
+        bbox.clamped = true;
+    }
+    if (clamped_y1, clamped_y2) != (y1, y2) {
+        bbox.h = clamped_y2 - clamped_y1;
+        bbox.cy = clamped_y1 + bbox.h / 2;
+        bbox.clamped = true;
+    }
+    bbox
+}
+
+// [cx, cy, w, h] mapped back from the `letterbox` padded image, translated by the tile
+// offset, then multiplied by the output `scale`
+fn coordinates(bbox: &BBox, options: &NmsOptions) -> [f32; 4] {
+    let [cx, cy, w, h] = [bbox.cx, bbox.cy, bbox.w, bbox.h].map(|c| c as f32);
+    let [cx, cy, w, h] = match options.letterbox {
+        Some((gain, pad_x, pad_y)) => [(cx - pad_x) / gain, (cy - pad_y) / gain, w / gain, h / gain],
+        None => [cx, cy, w, h],
+    };
+    [cx + options.offset_x, cy + options.offset_y, w, h].map(|c| c * options.scale)
+}
+
+//convert BBox to [cx, cy, w, h, prob, class_idx], plus the angle for oriented boxes
+fn bboxes_to_result(bboxes: Vec<BBox>, options: &NmsOptions) -> Vec<Vec<f32>> {
+    bboxes.into_iter().map(|bbox| {
+        let [cx, cy, w, h] = coordinates(&bbox, options);
+        let mut result = vec![cx, cy, w, h, bbox.prob, bbox.class as f32];
+        if options.obb {
+            result.push(bbox.angle);
+        }
+        result
+    }).collect()
+}
+
+// %{data: binary, count: detections, columns: 6}, the list rows packed as f32 (7 columns
+// with the angle of the oriented boxes) in the `endianness` byte order, the native one is
+// ready for `Nx.from_binary/2`. Without detections
+// `data` is an empty binary, `count` is 0 and `columns` is still given to reshape it to `{0, 6}`.
+fn bboxes_to_binary<'a>(env: Env<'a>, bboxes: Vec<BBox>, options: &NmsOptions) -> NifResult<Term<'a>> {
+    let columns = if options.obb { 7 } else { 6 };
+    let count = bboxes.len();
+    let f32size = std::mem::size_of::<f32>();
+
+    let mut data = OwnedBinary::new(count * columns * f32size).ok_or(rustler::Error::BadArg)?;
+    let values = bboxes_to_result(bboxes, options).into_iter().flatten();
+    for (bytes, value) in data.as_mut_slice().chunks_mut(f32size).zip(values) {
+        let value_bytes = match options.endianness {
+            Endianness::Native => value.to_ne_bytes(),
+            Endianness::Little => value.to_le_bytes(),
+            Endianness::Big => value.to_be_bytes(),
+        };
+        bytes.copy_from_slice(&value_bytes);
+    }
+
+    Term::map_from_pairs(env, &[
+        (atoms::data().encode(env), data.release(env).encode(env)),
+        (atoms::count().encode(env), count.encode(env)),
+        (atoms::columns().encode(env), columns.encode(env)),
+    ])
+}
+
+// The list rows interleaved in a single list, one list term instead of one for each detection.
+fn bboxes_to_flat<'a>(env: Env<'a>, bboxes: Vec<BBox>, options: &NmsOptions) -> NifResult<Term<'a>> {
+    let columns = if options.obb { 7 } else { 6 };
+    let count = bboxes.len();
+    let data: Vec<f32> = bboxes_to_result(bboxes, options).into_iter().flatten().collect();
+
+    Term::map_from_pairs(env, &[
+        (atoms::data().encode(env), data.encode(env)),
+        (atoms::count().encode(env), count.encode(env)),
+        (atoms::columns().encode(env), columns.encode(env)),
+    ])
+}
+
+// [class_idx, cx, cy, w, h] like the rows of the YOLO label files, the class an integer and
+// the model coordinates normalized by the `image_size`
+fn bboxes_to_labels<'a>(env: Env<'a>, bboxes: Vec<BBox>, options: &NmsOptions) -> Vec<Vec<Term<'a>>> {
+    let (width, height) = options.image_size.map_or((1.0, 1.0), |(w, h)| (w as f32, h as f32));
+    bboxes.into_iter().map(|bbox| {
+        let normalized = [
+            bbox.cx as f32 / width,
+            bbox.cy as f32 / height,
+            bbox.w as f32 / width,
+            bbox.h as f32 / height,
+        ];
+        std::iter::once(bbox.class.encode(env))
+            .chain(normalized.iter().map(|c| c.encode(env)))
+            .collect()
+    }).collect()
+}
+
+//convert BBox to [cx, cy, w, h], plus the angle for oriented boxes
+fn bboxes_to_boxes(bboxes: Vec<BBox>, options: &NmsOptions) -> Vec<Vec<f32>> {
+    bboxes.into_iter().map(|bbox| {
+        let mut result = coordinates(&bbox, options).to_vec();
+        if options.obb {
+            result.push(bbox.angle);
+        }
+        result
+    }).collect()
+}
+
+//convert BBox to %{cx: _, cy: _, w: _, h: _, prob: _, class_idx: _} plus the requested extra keys
+fn bbox_to_map<'a>(env: Env<'a>, bbox: BBox, options: &NmsOptions) -> NifResult<Term<'a>> {
+    let [cx, cy, w, h] = coordinates(&bbox, options);
+    let mut map = Term::map_from_pairs(env, &[
+        (atoms::cx(), cx),
+        (atoms::cy(), cy),
+        (atoms::w(), w),
+        (atoms::h(), h),
+        (atoms::prob(), bbox.prob),
+    ])?
+    .map_put(atoms::class_idx(), bbox.class)?;
+
+    if options.obb {
+        map = map.map_put(atoms::angle(), bbox.angle)?;
+    }
+
+    if options.corners {
+        map = map
+            .map_put(atoms::x1(), cx - w / 2.0)?
+            .map_put(atoms::y1(), cy - h / 2.0)?
+            .map_put(atoms::x2(), cx + w / 2.0)?
+            .map_put(atoms::y2(), cy + h / 2.0)?;
+    }
+
+    if options.group_column {
+        map = map.map_put(atoms::group(), bbox.group)?;
+    }
+
+    // nil for the classes out of the names table
+    if let Some(class_names) = &options.class_names {
+        map = map.map_put(atoms::label(), class_names.get(bbox.class as usize))?;
+    }
+
+    // the same class always draws the same color of the overlay palette
+    if let Some(palette_size) = options.palette_size {
+        map = map.map_put(atoms::color_index(), bbox.class as usize % palette_size)?;
+    }
+
+    if options.row_index {
+        map = map.map_put(atoms::row_index(), bbox.row)?;
+    }
+
+    if options.clamped {
+        map = map.map_put(atoms::clamped(), bbox.clamped)?;
+    }
+
+    if options.temporal_iou.is_some() {
+        map = map.map_put(atoms::track_id(), bbox.track_id)?;
+    }
+
+    if options.display {
+        let display_threshold = options.display_threshold.unwrap_or(options.prob_threshold);
+        map = map.map_put(atoms::display(), bbox.prob >= display_threshold)?;
+    }
+
+    if options.class_probs {
+        map = map.map_put(atoms::class_probs(), &bbox.class_probs)?;
+    }
+
+    if options.top_k_classes > 1 {
+        map = map.map_put(atoms::top_classes(), &bbox.top_classes)?;
+    }
+
+    // the scores of the requested classes, whatever the class of the box, without the classes
+    // out of the class probabilities
+    if let Some(classes) = &options.class_scores {
+        let scores: HashMap<u16, f32> = classes
+            .iter()
+            .filter_map(|&class| bbox.class_probs.get(class as usize).map(|&score| (class, score)))
+            .collect();
+        map = map.map_put(atoms::class_scores(), scores)?;
+    }
+
+    Ok(map)
+}
@@ -0,0 +1,127 @@
+use crate::{BBox, OverlapMetric};
+
+type Point = (f32, f32);
+
+// Below this value two edges are considered parallel.
+const EPSILON: f32 = 1e-6;
+
+// IoU (or the other `metric`) of two oriented boxes, rotated by `angle` radians around their center.
+// The intersection polygon is computed clipping the corners of `a` by the edges of `b`
+// (Sutherland–Hodgman), its area with the shoelace formula.
+pub(crate) fn rotated_iou(a: &BBox, b: &BBox, metric: OverlapMetric) -> f32 {
+    let a_corners = corners(a);
+    let b_corners = corners(b);
+
+    // in f32, the i32 product of the sides overflows above 46341 pixels
+    let a_area = a.w as f32 * a.h as f32;
+    let b_area = b.w as f32 * b.h as f32;
+    if a_area <= 0.0 || b_area <= 0.0 || separated(&a_corners, &b_corners) {
+        return 0.0;
+    }
+
+    let intersection_area = polygon_area(&clip(&a_corners, &b_corners)).min(a_area.min(b_area));
+    let denominator = match metric {
+        OverlapMetric::Iou => a_area + b_area - intersection_area,
+        OverlapMetric::Iomin => a_area.min(b_area),
+        OverlapMetric::Iomax => a_area.max(b_area),
+    };
+
+    if denominator <= 0.0 {
+        0.0
+    } else {
+        (intersection_area / denominator).clamp(0.0, 1.0)
+    }
+}
+
+// Corners in counter-clockwise order.
+fn corners(bbox: &BBox) -> [Point; 4] {
+    let (sin, cos) = bbox.angle.sin_cos();
+    let (hw, hh) = (bbox.w as f32 / 2.0, bbox.h as f32 / 2.0);
+    let (cx, cy) = (bbox.cx as f32, bbox.cy as f32);
+
+    [(-hw, -hh), (hw, -hh), (hw, hh), (-hw, hh)]
+        .map(|(x, y)| (cx + x * cos - y * sin, cy + x * sin + y * cos))
+}
+
+// Separating axis test: the boxes don't overlap if their projections on one
+// of the edge normals don't overlap. Cheap early exit before the clipping.
+fn separated(a: &[Point; 4], b: &[Point; 4]) -> bool {
+    [a, b].iter().any(|polygon| {
+        (0..2).any(|i| {
+            let (p, q) = (polygon[i], polygon[i + 1]);
+            let axis = (q.1 - p.1, p.0 - q.0);
+            let (a_min, a_max) = project(a, axis);
+            let (b_min, b_max) = project(b, axis);
+            a_max <= b_min || b_max <= a_min
+        })
+    })
+}
+
+fn project(polygon: &[Point; 4], axis: Point) -> (f32, f32) {
+    polygon.iter().fold((f32::MAX, f32::MIN), |(min, max), p| {
+        let d = p.0 * axis.0 + p.1 * axis.1;
+        (min.min(d), max.max(d))
+    })
+}
+
+fn clip(subject: &[Point; 4], clipper: &[Point; 4]) -> Vec<Point> {
+    let mut output: Vec<Point> = subject.to_vec();
+
+    for i in 0..clipper.len() {
+        let (edge_start, edge_end) = (clipper[i], clipper[(i + 1) % clipper.len()]);
+        let input = std::mem::take(&mut output);
+
+        for j in 0..input.len() {
+            let current = input[j];
+            let previous = input[(j + input.len() - 1) % input.len()];
+            let current_inside = cross(edge_start, edge_end, current) >= 0.0;
+            let previous_inside = cross(edge_start, edge_end, previous) >= 0.0;
+
+            if current_inside {
+                if !previous_inside {
+                    output.push(intersection(previous, current, edge_start, edge_end));
+                }
+                output.push(current);
+            } else if previous_inside {
+                output.push(intersection(previous, current, edge_start, edge_end));
+            }
+        }
+
+        if output.is_empty() {
+            break;
+        }
+    }
+
+    output
+}
+
+// > 0 when `p` is on the left of the `a -> b` edge (inside, for counter-clockwise polygons).
+fn cross(a: Point, b: Point, p: Point) -> f32 {
+    (b.0 - a.0) * (p.1 - a.1) - (b.1 - a.1) * (p.0 - a.0)
+}
+
+// Intersection of the segment `p -> q` with the line through `a -> b`.
+fn intersection(p: Point, q: Point, a: Point, b: Point) -> Point {
+    let d1 = cross(a, b, p);
+    let d2 = cross(a, b, q);
+    let denominator = d1 - d2;
+    // near-parallel: the segment lies on the edge line, `p` is as good as any point
+    if denominator.abs() < EPSILON {
+        return p;
+    }
+    let t = d1 / denominator;
+    (p.0 + t * (q.0 - p.0), p.1 + t * (q.1 - p.1))
+}
+
+fn polygon_area(polygon: &[Point]) -> f32 {
+    if polygon.len() < 3 {
+        return 0.0;
+    }
+    let twice_area: f32 = (0..polygon.len())
+        .map(|i| {
+            let (p, q) = (polygon[i], polygon[(i + 1) % polygon.len()]);
+            p.0 * q.1 - q.0 * p.1
+        })
+        .sum();
+    twice_area.abs() / 2.0
+}
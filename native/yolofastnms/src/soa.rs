@@ -0,0 +1,170 @@
+use std::cmp::Ordering;
+
+use crate::{
+    BBox, IOU_FIXED_STEPS, KeptBoxes, NmsOptions, box_area, corners, f32_overlap, intersection_area,
+    overlap_denominator, suppresses, zero_area_iou,
+};
+
+// The boxes kept so far by the hard NMS of a group, as a struct of arrays: the corners and the
+// area of each box in contiguous arrays. The inner loop of the NMS, the overlaps of a candidate
+// with all the kept boxes, reads them sequentially instead of striding over the `BBox` structs
+// and their heap fields, and the compiler can vectorize it. Only the kept boxes are laid out
+// this way, the candidates are read once each. Same arithmetic as `calc_iou`.
+struct Columns<'b> {
+    x1: Vec<i64>,
+    y1: Vec<i64>,
+    x2: Vec<i64>,
+    y2: Vec<i64>,
+    area: Vec<i64>,
+    // only read for the zero-area pairs
+    bboxes: Vec<&'b BBox>,
+}
+
+impl<'b> Columns<'b> {
+    fn new() -> Self {
+        Columns {
+            x1: Vec::new(),
+            y1: Vec::new(),
+            x2: Vec::new(),
+            y2: Vec::new(),
+            area: Vec::new(),
+            bboxes: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, bbox: &'b BBox, edge: i64) {
+        let (x1, y1, x2, y2) = corners(bbox);
+        self.x1.push(x1);
+        self.y1.push(y1);
+        self.x2.push(x2);
+        self.y2.push(y2);
+        self.area.push(box_area(bbox, edge));
+        self.bboxes.push(bbox);
+    }
+
+    // The `(intersection, denominator)` of the `iou_areas` of `bbox` with each kept box.
+    fn areas<'a>(&'a self, bbox: &BBox, options: &'a NmsOptions) -> impl Iterator<Item = (i64, i64)> + 'a {
+        let edge = options.pixel_inclusive as i64;
+        let bbox_corners = corners(bbox);
+        let area = box_area(bbox, edge);
+
+        (0..self.area.len()).map(move |j| {
+            let kept_corners = (self.x1[j], self.y1[j], self.x2[j], self.y2[j]);
+            let intersection = intersection_area(bbox_corners, kept_corners, edge);
+            (intersection, overlap_denominator(intersection, area, self.area[j], options))
+        })
+    }
+}
+
+// The kept boxes of the hard NMS of the axis-aligned boxes with the f32 IoU.
+pub(crate) struct IouColumns<'b, 'o> {
+    columns: Columns<'b>,
+    options: &'o NmsOptions,
+}
+
+impl<'o> IouColumns<'_, 'o> {
+    pub(crate) fn new(options: &'o NmsOptions) -> Self {
+        IouColumns { columns: Columns::new(), options }
+    }
+}
+
+impl<'b> KeptBoxes<'b> for IouColumns<'b, '_> {
+    type Overlap = f32;
+
+    fn push(&mut self, bbox: &'b BBox) {
+        self.columns.push(bbox, self.options.pixel_inclusive as i64);
+    }
+
+    fn overlaps(&self, bbox: &BBox, overlaps: &mut Vec<f32>) {
+        overlaps.clear();
+        overlaps.extend(
+            self.columns
+                .areas(bbox, self.options)
+                .zip(&self.columns.bboxes)
+                .map(|((intersection, denominator), kept)| {
+                    f32_overlap(intersection, denominator, bbox, kept, self.options)
+                })
+        );
+    }
+
+    fn suppresses(&self, overlap: f32) -> bool {
+        suppresses(overlap, self.options)
+    }
+
+    fn value(&self, overlap: f32) -> f32 {
+        overlap
+    }
+}
+
+// The kept boxes of the hard NMS of the axis-aligned boxes with the fixed-point IoU of
+// `calc_iou_fixed`. The overlaps are kept as fractions, compared by cross-multiplying, and the
+// threshold is a number of steps, so the inner loop has no division.
+pub(crate) struct FixedIouColumns<'b, 'o> {
+    columns: Columns<'b>,
+    options: &'o NmsOptions,
+    // the fewest steps of a suppressing IoU
+    min_steps: i128,
+}
+
+impl<'o> FixedIouColumns<'_, 'o> {
+    pub(crate) fn new(options: &'o NmsOptions) -> Self {
+        // the IoU is `steps / IOU_FIXED_STEPS`: above the threshold, or also at it when inclusive
+        let threshold_steps = options.iou_threshold * IOU_FIXED_STEPS as f32;
+        let min_steps = if options.threshold_inclusive {
+            threshold_steps.ceil() as i128
+        } else {
+            threshold_steps.floor() as i128 + 1
+        };
+        FixedIouColumns { columns: Columns::new(), options, min_steps }
+    }
+}
+
+// `intersection / denominator`, with a positive denominator.
+#[derive(Clone, Copy)]
+pub(crate) struct Fraction {
+    intersection: i64,
+    denominator: i64,
+}
+
+impl PartialEq for Fraction {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for Fraction {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let a = self.intersection as i128 * other.denominator as i128;
+        let b = other.intersection as i128 * self.denominator as i128;
+        Some(a.cmp(&b))
+    }
+}
+
+impl<'b> KeptBoxes<'b> for FixedIouColumns<'b, '_> {
+    type Overlap = Fraction;
+
+    fn push(&mut self, bbox: &'b BBox) {
+        self.columns.push(bbox, self.options.pixel_inclusive as i64);
+    }
+
+    fn overlaps(&self, bbox: &BBox, overlaps: &mut Vec<Fraction>) {
+        overlaps.clear();
+        overlaps.extend(
+            self.columns
+                .areas(bbox, self.options)
+                .zip(&self.columns.bboxes)
+                .map(|((intersection, denominator), kept)| match denominator {
+                    0 => Fraction { intersection: zero_area_iou(bbox, kept, self.options) as i64, denominator: 1 },
+                    _ => Fraction { intersection, denominator },
+                })
+        );
+    }
+
+    fn suppresses(&self, overlap: Fraction) -> bool {
+        overlap.intersection as i128 * IOU_FIXED_STEPS as i128 >= self.min_steps * overlap.denominator as i128
+    }
+
+    fn value(&self, overlap: Fraction) -> f32 {
+        (overlap.intersection * IOU_FIXED_STEPS / overlap.denominator) as f32 / IOU_FIXED_STEPS as f32
+    }
+}
@@ -0,0 +1,114 @@
+use rustler::{Atom, Binary, Env, NifResult, ResourceArc, Term};
+
+use std::sync::Mutex;
+
+use crate::{
+    BBox, NmsOptions, Trace, encode_bboxes, extend_bboxes, filter_bboxes, read_bboxes_columnwise, read_detections,
+    suppress, truncate_to_budget,
+};
+
+mod atoms {
+    rustler::atoms! {
+        ok,
+        invalid_chunk_size,
+        max_candidates_exceeded,
+    }
+}
+
+// Candidate buffer filled chunk by chunk and consumed by `stream_finalize`.
+// Only the boxes passing `prob_threshold` are kept, so the memory used is bounded
+// by `max_candidates` and not by the size of the model output.
+struct CandidateStream {
+    columns: usize,
+    max_candidates: usize,
+    // parsing options applied to every chunk
+    options: NmsOptions,
+    buffer: Mutex<StreamBuffer>,
+}
+
+#[derive(Default)]
+struct StreamBuffer {
+    candidates: Vec<BBox>,
+    // rows pushed so far, to index the detections across chunks
+    rows: usize,
+}
+
+#[rustler::resource_impl]
+impl rustler::Resource for CandidateStream {}
+
+#[rustler::nif(name = "stream_new_nif")]
+fn stream_new(
+    columns: usize,
+    max_candidates: usize,
+    options: NmsOptions
+) -> ResourceArc<CandidateStream> {
+    ResourceArc::new(CandidateStream {
+        columns,
+        max_candidates,
+        // the rows window is of a single tensor, every chunk is read whole
+        options: NmsOptions { row_offset: 0, row_count: None, ..options },
+        buffer: Mutex::new(StreamBuffer::default()),
+    })
+}
+
+// Parses a chunk and appends its candidates to the buffer.
+// The chunk is `{rows, columns}`, or `{columns, rows}` when the stream transposes.
+// A chunk that would overflow `max_candidates` is rejected as a whole and the buffer is left untouched.
+#[rustler::nif(name = "stream_push_nif")]
+fn stream_push(stream: ResourceArc<CandidateStream>, binary: Binary) -> NifResult<Result<usize, Atom>> {
+    let row_size = stream.columns * std::mem::size_of::<f32>();
+    if row_size == 0 || !binary.len().is_multiple_of(row_size) {
+        return Ok(Err(atoms::invalid_chunk_size()));
+    }
+
+    // same readers and checks of the columns as `run`, the rows without any class probability
+    // have no candidates
+    let detections = binary.len() / row_size;
+    let options = &stream.options;
+    let mut chunk_bboxes = Vec::new();
+    if options.transpose {
+        read_bboxes_columnwise(binary.as_slice(), stream.columns, detections, options, &mut chunk_bboxes)?;
+    } else {
+        let matrix = read_detections(binary.as_slice(), detections, stream.columns, options)?;
+        extend_bboxes(&matrix, options, &mut chunk_bboxes);
+    }
+    let mut chunk_bboxes = filter_bboxes(chunk_bboxes, options.prob_threshold);
+
+    let mut buffer = stream.buffer.lock().unwrap();
+    if buffer.candidates.len() + chunk_bboxes.len() > stream.max_candidates {
+        return Ok(Err(atoms::max_candidates_exceeded()));
+    }
+    // row indexes relative to the whole stream
+    for bbox in chunk_bboxes.iter_mut() {
+        bbox.row += buffer.rows;
+    }
+    buffer.rows += detections;
+    buffer.candidates.extend(chunk_bboxes);
+
+    Ok(Ok(buffer.candidates.len()))
+}
+
+// Runs NMS over all the buffered candidates. The buffer is kept, call `stream_reset` to empty it.
+// The parsing options (`prob_threshold`, `transpose`...) are the ones given to `stream_new`.
+#[rustler::nif(name = "stream_finalize_nif")]
+fn stream_finalize<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<CandidateStream>,
+    options: NmsOptions
+) -> NifResult<Term<'a>> {
+    let mut candidates = stream.buffer.lock().unwrap().candidates.clone();
+    let mut trace = Trace::default();
+    if let Some(budget) = options.candidate_budget {
+        trace.truncated = truncate_to_budget(&mut candidates, budget);
+    }
+    let final_bboxes = suppress(candidates, &options, &mut trace.suppressed);
+    encode_bboxes(env, final_bboxes, trace, &options)
+}
+
+#[rustler::nif(name = "stream_reset_nif")]
+fn stream_reset(stream: ResourceArc<CandidateStream>) -> Atom {
+    let mut buffer = stream.buffer.lock().unwrap();
+    buffer.candidates = Vec::new();
+    buffer.rows = 0;
+    atoms::ok()
+}
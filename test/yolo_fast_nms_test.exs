@@ -75,6 +75,2619 @@ defmodule YoloFastNMSTest do
              |> MapSet.new()
   end
 
+  test "degenerate shapes return no detections" do
+    for transpose <- [true, false] do
+      # zero rows or columns
+      assert [] == YoloFastNMS.run_with_binary(<<>>, 0.25, 0.5, 0, 10, transpose)
+      assert [] == YoloFastNMS.run_with_binary(<<>>, 0.25, 0.5, 10, 0, transpose)
+      assert [] == YoloFastNMS.run_with_binary(<<>>, 0.25, 0.5, 0, 0, transpose)
+    end
+
+    row = detection_row([10, 20, 10, 20], 0.7, 1, 6)
+
+    # 1x10: a single detection, or 10 detections without class probabilities when transposed
+    one_by_n = Nx.tensor([row], type: {:f, 32})
+    assert [[10, 20, 10, 20, 0.7, 1]] ==
+             one_by_n |> YoloFastNMS.run(transpose: false) |> round_results()
+
+    assert [] == YoloFastNMS.run(one_by_n, transpose: true)
+
+    # 10x1: a single detection when transposed, 10 detections without class probabilities otherwise
+    n_by_one = Nx.transpose(one_by_n, axes: [1, 0])
+    assert [[10, 20, 10, 20, 0.7, 1]] ==
+             n_by_one |> YoloFastNMS.run(transpose: true) |> round_results()
+
+    assert [] == YoloFastNMS.run(n_by_one, transpose: false)
+
+    # too few columns even for the bounding box
+    assert [] == YoloFastNMS.run(Nx.tensor([[1, 2, 3]], type: {:f, 32}), transpose: false)
+  end
+
+  test "sanity check catches xyxy boxes parsed as cxcywh" do
+    # the same boxes as `cx, cy, w, h` and as `x1, y1, x2, y2`
+    cxcywh = [[100, 100, 40, 40], [300, 200, 60, 20], [50, 400, 20, 80], [500, 500, 100, 100]]
+
+    xyxy =
+      Enum.map(cxcywh, fn [cx, cy, w, h] -> [cx - w / 2, cy - h / 2, cx + w / 2, cy + h / 2] end)
+
+    tensor = fn boxes ->
+      boxes
+      |> Enum.map(&detection_row(&1, 0.9, 0, 2))
+      |> Nx.tensor(type: {:f, 32})
+    end
+
+    assert 4 == length(YoloFastNMS.run(tensor.(cxcywh), transpose: false, sanity_check: true))
+
+    assert_raise ErlangError, ~r/cx, cy, w, h/, fn ->
+      YoloFastNMS.run(tensor.(xyxy), transpose: false, sanity_check: true)
+    end
+
+    # unchecked by default
+    assert [_ | _] = YoloFastNMS.run(tensor.(xyxy), transpose: false)
+  end
+
+  test "only the rows window is considered" do
+    rows =
+      [
+        detection_row([0, 0, 10, 20], 0.4, 0, 6),
+        detection_row([10, 40, 10, 20], 0.5, 1, 6),
+        detection_row([20, 60, 10, 20], 0.6, 2, 6),
+        detection_row([30, 80, 10, 20], 0.7, 3, 6)
+      ]
+
+    model_output = Nx.tensor(rows, type: {:f, 32})
+    transposed = Nx.transpose(model_output, axes: [1, 0])
+
+    assert [[10, 40, 10, 20, 0.5, 1], [20, 60, 10, 20, 0.6, 2]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, row_offset: 1, row_count: 2)
+             |> round_results()
+             |> Enum.sort()
+
+    assert [[10, 40, 10, 20, 0.5, 1], [20, 60, 10, 20, 0.6, 2]] ==
+             transposed
+             |> YoloFastNMS.run(row_offset: 1, row_count: 2)
+             |> round_results()
+             |> Enum.sort()
+
+    assert [[30, 80, 10, 20, 0.7, 3]] ==
+             transposed
+             |> YoloFastNMS.run(row_offset: 3)
+             |> round_results()
+
+    assert_raise ErlangError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, row_offset: 3, row_count: 2)
+    end
+
+    # the window end overflows
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, row_offset: 18_446_744_073_709_551_615, row_count: 2)
+    end
+  end
+
+  test "input_sorted skips sorting the pre-sorted detections" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.9, 0, 3),
+        detection_row([50, 50, 10, 20], 0.8, 1, 3),
+        detection_row([1, 0, 10, 20], 0.7, 0, 3),
+        detection_row([300, 0, 10, 20], 0.6, 2, 3),
+        detection_row([51, 50, 10, 20], 0.5, 1, 3),
+        detection_row([200, 0, 10, 20], 0.4, 0, 3)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    for options <- [[], [agnostic: true]] do
+      expected =
+        model_output
+        |> YoloFastNMS.run([transpose: false] ++ options)
+        |> round_results()
+        |> MapSet.new()
+
+      assert MapSet.new([
+               [0, 0, 10, 20, 0.9, 0],
+               [50, 50, 10, 20, 0.8, 1],
+               [300, 0, 10, 20, 0.6, 2],
+               [200, 0, 10, 20, 0.4, 0]
+             ]) == expected
+
+      assert expected ==
+               model_output
+               |> YoloFastNMS.run([transpose: false, input_sorted: true] ++ options)
+               |> round_results()
+               |> MapSet.new()
+    end
+  end
+
+  test "max_candidates_per_class truncates the crowded class" do
+    # far apart boxes, none is suppressed
+    crowded = for i <- 1..10, do: detection_row([i * 50, 0, 10, 20], 0.3 + i * 0.05, 0, 2)
+
+    model_output =
+      Nx.tensor(crowded ++ [detection_row([0, 300, 10, 20], 0.5, 1, 2)], type: {:f, 32})
+
+    assert 11 == length(YoloFastNMS.run(model_output, transpose: false))
+
+    assert MapSet.new([
+             [400, 0, 10, 20, 0.7, 0],
+             [450, 0, 10, 20, 0.75, 0],
+             [500, 0, 10, 20, 0.8, 0],
+             [0, 300, 10, 20, 0.5, 1]
+           ]) ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, max_candidates_per_class: 3)
+             |> round_results()
+             |> MapSet.new()
+  end
+
+  test "tile offset translates the detections to the global coordinates" do
+    model_output = Nx.tensor([detection_row([100, 50, 40, 20], 0.9, 0, 2)], type: {:f, 32})
+
+    assert [[1380, 770, 40, 20, 0.9, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, offset_x: 1280, offset_y: 720)
+             |> round_results()
+
+    assert [[690, 385, 20, 10, 0.9, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, offset_x: 1280, offset_y: 720, scale: 0.5)
+             |> round_results()
+  end
+
+  test "run_batch rescales each image with its own letterbox" do
+    image = [detection_row([100, 100, 40, 40], 0.9, 0, 2)]
+    model_output = Nx.tensor([image, image], type: {:f, 32})
+
+    assert [[[200, 200, 80, 80, 0.9, 0]], [[45, 40, 20, 20, 0.9, 0]]] ==
+             model_output
+             |> YoloFastNMS.run_batch(transpose: false, letterbox: [{0.5, 0, 0}, {2, 10, 20}])
+             |> Enum.map(&round_results/1)
+
+    # a single letterbox is shared by all the images
+    assert [[[45, 40, 20, 20, 0.9, 0]], [[45, 40, 20, 20, 0.9, 0]]] ==
+             model_output
+             |> YoloFastNMS.run_batch(transpose: false, letterbox: {2, 10, 20})
+             |> Enum.map(&round_results/1)
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run_batch(model_output, transpose: false, letterbox: [{2, 10, 20}])
+    end
+  end
+
+  test "run_batch_chunk returns the results of the batch chunk by chunk" do
+    model_output =
+      for i <- 1..3 do
+        [detection_row([100 * i, 100, 40, 40], 0.9, 0, 2)]
+      end
+      |> Nx.tensor(type: {:f, 32})
+
+    letterbox = [{1, 0, 0}, {1, 0, 0}, {2, 0, 0}]
+    options = [transpose: false, letterbox: letterbox]
+
+    assert {first_chunk, 2} = YoloFastNMS.run_batch_chunk(model_output, 0, 2, options)
+    assert {last_chunk, nil} = YoloFastNMS.run_batch_chunk(model_output, 2, 2, options)
+
+    assert [
+             [[100, 100, 40, 40, 0.9, 0]],
+             [[200, 100, 40, 40, 0.9, 0]],
+             [[150, 50, 20, 20, 0.9, 0]]
+           ] == Enum.map(first_chunk ++ last_chunk, &round_results/1)
+
+    assert first_chunk ++ last_chunk == YoloFastNMS.run_batch(model_output, options)
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run_batch_chunk(model_output, 4, 2, options) end
+  end
+
+  test "threshold_inclusive suppresses the overlaps exactly at the threshold" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        # IoU exactly 0.6
+        detection_row([110, 100, 40, 40], 0.8, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert 2 ==
+             model_output |> YoloFastNMS.run(transpose: false, iou_threshold: 0.6) |> length()
+
+    assert [[100, 100, 40, 40, 0.9, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, iou_threshold: 0.6, threshold_inclusive: true)
+             |> round_results()
+  end
+
+  test "top_n returns the most probable detections in prob order" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.4, 0),
+        detection_row([100, 100, 10, 20], 0.9, 1),
+        detection_row([200, 200, 10, 20], 0.6, 2),
+        detection_row([300, 300, 10, 20], 0.8, 0),
+        # suppressed by the 0.8 box
+        detection_row([301, 300, 10, 20], 0.7, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [
+             [100, 100, 10, 20, 0.9, 1],
+             [300, 300, 10, 20, 0.8, 0],
+             [200, 200, 10, 20, 0.6, 2]
+           ] ==
+             model_output
+             |> YoloFastNMS.run(classes_count: 80, top_n: 3)
+             |> round_results()
+
+    assert [0.9, 0.8, 0.6, 0.4] ==
+             model_output
+             |> YoloFastNMS.run(classes_count: 80, top_n: 0)
+             |> round_results()
+             |> Enum.map(&Enum.at(&1, 4))
+  end
+
+  test "auto_transpose infers the orientation from the shape" do
+    rows =
+      [
+        detection_row([10, 10, 10, 20], 0.9, 1),
+        detection_row([100, 100, 10, 20], 0.8, 2)
+      ] ++ Enum.map(1..98, fn _ -> detection_row([200, 200, 10, 20], 0.01, 0) end)
+
+    expected = [[10, 10, 10, 20, 0.9, 1], [100, 100, 10, 20, 0.8, 2]]
+    options = [classes_count: 80, auto_transpose: true, metadata: true]
+
+    # {100, 84}, one detection for each row
+    row_major = Nx.tensor(rows, type: {:f, 32})
+    {detections, metadata} = YoloFastNMS.run(row_major, options)
+    assert expected == round_results(detections)
+    assert metadata.transpose == false
+
+    # {84, 100}, one detection for each column
+    column_major = Nx.transpose(row_major, axes: [1, 0])
+    {detections, metadata} = YoloFastNMS.run(column_major, options)
+    assert expected == round_results(detections)
+    assert metadata.transpose == true
+
+    # the explicit transpose is authoritative
+    {_detections, metadata} = YoloFastNMS.run(row_major, [transpose: true] ++ options)
+    assert metadata.transpose == true
+  end
+
+  test "format: :flat interleaves the list rows" do
+    model_output =
+      [
+        detection_row([10, 10, 10, 20], 0.9, 1),
+        detection_row([100, 100, 10, 20], 0.8, 2),
+        detection_row([200, 200, 10, 20], 0.7, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    nested = YoloFastNMS.run(model_output, classes_count: 80)
+
+    assert %{data: data, count: 3, columns: 6} =
+             YoloFastNMS.run(model_output, classes_count: 80, format: :flat)
+
+    assert nested == Enum.chunk_every(data, 6)
+
+    assert %{data: [], count: 0, columns: 6} =
+             YoloFastNMS.run(model_output, classes_count: 80, format: :flat, prob_threshold: 0.95)
+  end
+
+  test "class_tie_epsilon drops the rows with tied best classes" do
+    tied = List.replace_at(detection_row([10, 10, 10, 20], 0.6, 1), 4 + 3, 0.6)
+    near_tie = List.replace_at(detection_row([100, 100, 10, 20], 0.6, 2), 4 + 5, 0.59)
+    distinct = List.replace_at(detection_row([200, 200, 10, 20], 0.8, 0), 4 + 1, 0.3)
+
+    model_output =
+      [tied, near_tie, distinct]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert 3 == length(YoloFastNMS.run(model_output, classes_count: 80))
+
+    assert [[100, 100, 10, 20, 0.6, 2], [200, 200, 10, 20, 0.8, 0]] ==
+             model_output
+             |> YoloFastNMS.run(classes_count: 80, class_tie_epsilon: 0.0)
+             |> round_results()
+             |> Enum.sort_by(&Enum.at(&1, 0))
+
+    assert [[200, 200, 10, 20, 0.8, 0]] ==
+             model_output
+             |> YoloFastNMS.run(classes_count: 80, class_tie_epsilon: 0.02)
+             |> round_results()
+  end
+
+  test "threads gives the same result of the sequential NMS" do
+    :rand.seed(:exsss, {7, 8, 9})
+
+    model_output =
+      for _ <- 1..500 do
+        bbox = [100 * :rand.uniform(), 100 * :rand.uniform(), 10 + 20 * :rand.uniform(), 20]
+        bbox ++ List.replace_at(List.duplicate(0.0, 10), :rand.uniform(10) - 1, :rand.uniform())
+      end
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    options = [classes_count: 10, metadata: true, suppressed: true]
+    sequential = YoloFastNMS.run(model_output, options)
+
+    for threads <- [2, 3, 16] do
+      assert sequential == YoloFastNMS.run(model_output, [threads: threads] ++ options)
+    end
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, threads: 0) end
+  end
+
+  test "coverage counts the overlap of the boxes once" do
+    model_output =
+      [
+        # 20x20 boxes overlapping by 10x20, different classes so both are kept
+        detection_row([20, 20, 20, 20], 0.9, 0),
+        detection_row([30, 20, 20, 20], 0.8, 1)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    {_detections, metadata} =
+      YoloFastNMS.run(model_output,
+        classes_count: 80,
+        metadata: true,
+        coverage: true,
+        image_size: {100, 100}
+      )
+
+    assert metadata.coverage_area == 600.0
+    assert_in_delta metadata.coverage_fraction, 0.06, 1.0e-9
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, coverage: true) end
+  end
+
+  test "coverage and class_centroids are in the coordinates of the returned boxes" do
+    model_output =
+      [
+        detection_row([20, 20, 20, 20], 0.9, 0),
+        detection_row([30, 20, 20, 20], 0.8, 1)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    {[[cx, cy, _w, _h, _prob, 0], _other], metadata} =
+      YoloFastNMS.run(model_output,
+        classes_count: 80,
+        metadata: true,
+        coverage: true,
+        class_centroids: true,
+        image_size: {100, 100},
+        offset_x: 10,
+        scale: 2.0
+      )
+
+    # (20 + 10) * 2, 20 * 2
+    assert {cx, cy} == {60.0, 40.0}
+    assert %{0 => {60.0, 40.0}, 1 => {80.0, 40.0}} = metadata.class_centroids
+    assert metadata.coverage_area == 2400.0
+    assert_in_delta metadata.coverage_fraction, 0.06, 1.0e-9
+  end
+
+  test "run_quantized matches the equivalent f32 input" do
+    boxes = [[10, 10, 10, 20], [11, 10, 10, 20], [100, 100, 10, 20], [200, 200, 10, 20]]
+    scores = [[230, 0, 0], [200, 0, 10], [0, 128, 64], [0, 0, 20]]
+
+    model_output =
+      Enum.zip_with(boxes, scores, fn bbox, class_scores ->
+        bbox ++ Enum.map(class_scores, &(&1 / 255))
+      end)
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    expected = model_output |> YoloFastNMS.run(classes_count: 3) |> round_results() |> Enum.sort()
+    assert length(expected) == 2
+
+    assert expected ==
+             YoloFastNMS.run_quantized(
+               boxes |> Nx.tensor(type: {:f, 32}) |> Nx.transpose(),
+               scores |> Nx.tensor(type: {:u, 8}) |> Nx.transpose()
+             )
+             |> round_results()
+             |> Enum.sort()
+
+    # row-major with transpose: false
+    assert expected ==
+             YoloFastNMS.run_quantized(
+               Nx.tensor(boxes, type: {:f, 32}),
+               Nx.tensor(scores, type: {:u, 8}),
+               transpose: false
+             )
+             |> round_results()
+             |> Enum.sort()
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run_quantized(
+        Nx.tensor(boxes, type: {:f, 32}),
+        Nx.tensor(Enum.take(scores, 3), type: {:u, 8}),
+        transpose: false
+      )
+    end
+
+    unsupported = [
+      row_offset: 1,
+      row_count: 2,
+      byte_offset: 4,
+      valid_columns: 2,
+      auto_transpose: true,
+      group_column: true
+    ]
+
+    for option <- unsupported do
+      assert_raise ArgumentError, ~r/doesn't support/, fn ->
+        YoloFastNMS.run_quantized(
+          Nx.tensor(boxes, type: {:f, 32}),
+          Nx.tensor(scores, type: {:u, 8}),
+          [transpose: false, option]
+        )
+      end
+    end
+
+    # the columns are checked like run/2, here 4 box features and 3 class scores
+    for options <- [[score_column: 7, class_column: 4], [objectness: true]] do
+      assert_raise ArgumentError, fn ->
+        YoloFastNMS.run_quantized(
+          Nx.tensor(boxes, type: {:f, 32}),
+          Nx.tensor(scores, type: {:u, 8}),
+          [transpose: false] ++ options
+        )
+      end
+    end
+  end
+
+  test "candidate_budget truncates the candidates and flags it" do
+    model_output =
+      [
+        detection_row([10, 10, 10, 20], 0.5, 0),
+        detection_row([100, 100, 10, 20], 0.9, 1),
+        detection_row([200, 200, 10, 20], 0.6, 2),
+        detection_row([300, 300, 10, 20], 0.8, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    options = [classes_count: 80, metadata: true]
+
+    {detections, metadata} = YoloFastNMS.run(model_output, [candidate_budget: 2] ++ options)
+    assert metadata.truncated
+    assert [0.8, 0.9] == detections |> round_results() |> Enum.map(&Enum.at(&1, 4)) |> Enum.sort()
+
+    {detections, metadata} = YoloFastNMS.run(model_output, [candidate_budget: 4] ++ options)
+    refute metadata.truncated
+    assert length(detections) == 4
+  end
+
+  test "corners are consistent with the center and the size" do
+    model_output =
+      [
+        detection_row([10, 20, 10, 20], 0.9, 1),
+        detection_row([100, 100, 31, 15], 0.8, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    detections = YoloFastNMS.run(model_output, classes_count: 80, format: :map, corners: true)
+    assert length(detections) == 2
+
+    for %{cx: cx, cy: cy, w: w, h: h, x1: x1, y1: y1, x2: x2, y2: y2} <- detections do
+      assert_in_delta x1, cx - w / 2, 1.0e-4
+      assert_in_delta y1, cy - h / 2, 1.0e-4
+      assert_in_delta x2 - x1, w, 1.0e-4
+      assert_in_delta y2 - y1, h, 1.0e-4
+    end
+
+    refute Map.has_key?(hd(YoloFastNMS.run(model_output, classes_count: 80, format: :map)), :x1)
+  end
+
+  test "group_column suppresses only within the same group" do
+    model_output =
+      [
+        # overlapping boxes of tiles 0 and 1 on the tile border
+        detection_row([100, 100, 20, 20], 0.9, 0) ++ [0],
+        detection_row([102, 100, 20, 20], 0.8, 0) ++ [1],
+        # suppressed in tile 1
+        detection_row([103, 101, 20, 20], 0.7, 0) ++ [1]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [%{group: 0, prob: 0.9}, %{group: 1, prob: 0.8}] =
+             model_output
+             |> YoloFastNMS.run(group_column: true, format: :map)
+             |> Enum.map(&%{&1 | prob: Float.round(&1.prob, 2)})
+             |> Enum.sort_by(& &1.group)
+
+    # without the groups, the tile 1 boxes are suppressed too
+    assert [[100, 100, 20, 20, 0.9, 0]] ==
+             model_output
+             |> Nx.slice_along_axis(0, 84, axis: 0)
+             |> YoloFastNMS.run(classes_count: 80)
+             |> round_results()
+  end
+
+  test "parse_mode: :lenient skips and counts the malformed rows" do
+    model_output =
+      [
+        detection_row([10, 10, 10, 20], 0.9, 1),
+        detection_row([:nan, 100, 10, 20], 0.8, 2),
+        detection_row([200, 200, -10, 20], 0.7, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    {detections, metadata} = YoloFastNMS.run(model_output, classes_count: 80, metadata: true)
+    assert [[10, 10, 10, 20, 0.9, 1]] == round_results(detections)
+    assert metadata.dropped_rows == 2
+
+    # same count in the row-major path
+    {_detections, metadata} =
+      model_output
+      |> Nx.transpose(axes: [1, 0])
+      |> YoloFastNMS.run(transpose: false, metadata: true)
+
+    assert metadata.dropped_rows == 2
+  end
+
+  test "the coordinates out of the i32 range are dropped instead of saturated" do
+    model_output =
+      [
+        detection_row([10, 10, 10, 20], 0.9, 1),
+        detection_row([1.0e30, 10, 10, 20], 0.95, 1),
+        detection_row([10, 10, 3.0e9, 3.0e9], 0.95, 1)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    {detections, metadata} = YoloFastNMS.run(model_output, metadata: true)
+    assert [[10, 10, 10, 20, 0.9, 1]] == round_results(detections)
+    assert metadata.dropped_rows == 2
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.iou([1.0e30, 10, 10, 20], [10, 10, 10, 20]) end
+  end
+
+  test "parse_mode: :strict raises on a malformed row" do
+    model_output =
+      [
+        detection_row([10, 10, 10, 20], 0.9, 1),
+        detection_row([200, 200, -10, 20], 0.7, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert_raise ErlangError, ~r/1 malformed rows/, fn ->
+      YoloFastNMS.run(model_output, parse_mode: :strict)
+    end
+
+    assert [[10, 10, 10, 20, 0.9, 1]] ==
+             model_output
+             |> Nx.slice_along_axis(0, 1, axis: 1)
+             |> YoloFastNMS.run(parse_mode: :strict)
+             |> round_results()
+  end
+
+  test "area_weights boosts the small boxes over the threshold" do
+    model_output =
+      [
+        # 8x8, below the 0.25 threshold without the boost
+        detection_row([10, 10, 8, 8], 0.2, 1),
+        # 40x40, not boosted
+        detection_row([100, 100, 40, 40], 0.2, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [] == YoloFastNMS.run(model_output, classes_count: 80)
+
+    assert [[10, 10, 8, 8, 0.3, 1]] ==
+             model_output
+             |> YoloFastNMS.run(classes_count: 80, area_weights: [{100, 1.5}, {400, 1.2}])
+             |> round_results()
+  end
+
+  test "area_weights can boost a prob over a prob_threshold above 1.0" do
+    model_output =
+      [detection_row([10, 10, 8, 8], 0.8, 1)]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    options = [prob_threshold: 1.1, area_weights: [{100, 1.5}]]
+
+    assert [] == YoloFastNMS.run(model_output, prob_threshold: 1.1)
+    assert [[10, 10, 8, 8, 1.2, 1]] == model_output |> YoloFastNMS.run(options) |> round_results()
+    assert {1, 1, 1} == YoloFastNMS.counts(model_output, options)
+  end
+
+  test "pack round-trips the detections through run_with_binary" do
+    detections = [[10, 10, 10, 20, 0.9, 3], [100, 100, 10, 20, 0.5, 0]]
+
+    for transpose <- [true, false], objectness <- [true, false] do
+      options = [transpose: transpose, objectness: objectness]
+      {binary, rows, columns} = YoloFastNMS.pack(detections, 6, options)
+      features = if objectness, do: 11, else: 10
+      assert {rows, columns} == if(transpose, do: {features, 2}, else: {2, features})
+
+      assert detections ==
+               binary
+               |> YoloFastNMS.run_with_binary(0.25, 0.5, rows, columns, transpose, objectness: objectness)
+               |> round_results()
+               |> Enum.sort_by(&Enum.at(&1, 0))
+    end
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.pack([[10, 10, 10, 20, 0.9, 6]], 6) end
+  end
+
+  test "class_min_sizes drops the boxes too small for their class" do
+    model_output =
+      [
+        # 8x8 box, noise for a person (class 0), plausible for a bird (class 14)
+        detection_row([10, 10, 8, 8], 0.9, 0),
+        detection_row([100, 100, 8, 8], 0.9, 14),
+        # large enough for any class
+        detection_row([200, 200, 30, 30], 0.8, 0),
+        # below the global minimum
+        detection_row([300, 300, 2, 2], 0.9, 1)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [[100, 100, 8, 8, 0.9, 14], [200, 200, 30, 30, 0.8, 0]] ==
+             model_output
+             |> YoloFastNMS.run(classes_count: 80, min_size: 4, class_min_sizes: %{0 => 10})
+             |> round_results()
+             |> Enum.sort()
+  end
+
+  test "precision: :fixed stays close to the float IoU" do
+    assert_in_delta YoloFastNMS.iou([100, 100, 40, 40], [110, 105, 40, 30]),
+                    YoloFastNMS.iou([100, 100, 40, 40], [110, 105, 40, 30], precision: :fixed),
+                    1 / 1024
+
+    :rand.seed(:exsss, {4, 5, 6})
+
+    model_output =
+      for _ <- 1..1000 do
+        bbox = [200 * :rand.uniform(), 200 * :rand.uniform(), 10 + 30 * :rand.uniform(), 25]
+        bbox ++ List.replace_at(List.duplicate(0.0, 3), :rand.uniform(3) - 1, :rand.uniform())
+      end
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    float = model_output |> YoloFastNMS.run(classes_count: 3) |> MapSet.new()
+    fixed = model_output |> YoloFastNMS.run(classes_count: 3, precision: :fixed) |> MapSet.new()
+
+    # only the boxes overlapping within 1/1024 of the threshold can diverge
+    divergent = MapSet.union(MapSet.difference(float, fixed), MapSet.difference(fixed, float))
+    assert MapSet.size(divergent) <= div(MapSet.size(float), 50)
+  end
+
+  test "precision: :fixed compares the IoU at the threshold like the float IoU" do
+    # IoU of exactly 0.5: intersection 200, union 400
+    model_output =
+      [detection_row([10, 10, 20, 20], 0.9, 0, 2), detection_row([10, 10, 20, 10], 0.8, 0, 2)]
+      |> Nx.tensor(type: {:f, 32})
+
+    for precision <- [:f32, :fixed], {inclusive, kept} <- [{false, 2}, {true, 1}] do
+      assert kept ==
+               model_output
+               |> YoloFastNMS.run(
+                 transpose: false,
+                 iou_threshold: 0.5,
+                 threshold_inclusive: inclusive,
+                 precision: precision
+               )
+               |> length()
+    end
+  end
+
+  test "duplicate_rows counts the repeated rows without filtering them" do
+    row = detection_row([10, 10, 10, 20], 0.9, 1)
+
+    model_output =
+      [row, detection_row([200, 200, 10, 20], 0.7, 0), row, row]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    {detections, metadata} =
+      YoloFastNMS.run(model_output, duplicate_rows: true, metadata: true)
+
+    assert [[10, 10, 10, 20, 0.9, 1], [200, 200, 10, 20, 0.7, 0]] ==
+             detections |> round_results() |> Enum.sort()
+
+    assert metadata.duplicate_rows == 2
+
+    # same count in the row-major path
+    {_detections, metadata} =
+      model_output
+      |> Nx.transpose(axes: [1, 0])
+      |> YoloFastNMS.run(transpose: false, duplicate_rows: true, metadata: true)
+
+    assert metadata.duplicate_rows == 2
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, duplicate_rows: true) end
+  end
+
+  test "class_centroids weights the centers of the kept boxes by their prob" do
+    model_output =
+      [
+        detection_row([100, 100, 10, 10], 0.75, 0),
+        detection_row([200, 300, 10, 10], 0.25, 0),
+        detection_row([400, 50, 10, 10], 0.5, 1)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    {_detections, metadata} =
+      YoloFastNMS.run(model_output, prob_threshold: 0.1, class_centroids: true, metadata: true)
+
+    assert %{0 => {cx0, cy0}, 1 => {cx1, cy1}} = metadata.class_centroids
+    # (0.75 * 100 + 0.25 * 200) / 1.0, (0.75 * 100 + 0.25 * 300) / 1.0
+    assert_in_delta 125.0, cx0, 1.0e-6
+    assert_in_delta 150.0, cy0, 1.0e-6
+    assert_in_delta 400.0, cx1, 1.0e-6
+    assert_in_delta 50.0, cy1, 1.0e-6
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, class_centroids: true) end
+  end
+
+  test "sort_order: :asc runs the greedy loop from the least probable box" do
+    # IoU 0.6 between the first two, 0.45 between the last two, 0.23 between the first and the last
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0),
+        detection_row([110, 100, 40, 40], 0.6, 0),
+        detection_row([125, 100, 40, 40], 0.4, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [[100, 100, 40, 40, 0.9, 0], [125, 100, 40, 40, 0.4, 0]] ==
+             model_output |> YoloFastNMS.run([]) |> round_results() |> Enum.sort()
+
+    assert [[110, 100, 40, 40, 0.6, 0], [125, 100, 40, 40, 0.4, 0]] ==
+             model_output
+             |> YoloFastNMS.run(sort_order: :asc)
+             |> round_results()
+             |> Enum.sort()
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, sort_order: :asc, method: :soft) end
+  end
+
+  test "run_scales/2 suppresses the scales together like their concatenation" do
+    scale = fn rows -> rows |> Nx.tensor(type: {:f, 32}) |> Nx.transpose(axes: [1, 0]) end
+
+    large =
+      scale.([detection_row([100, 100, 40, 40], 0.9, 0), detection_row([400, 400, 80, 80], 0.4, 2)])
+
+    small =
+      scale.([detection_row([104, 102, 40, 40], 0.7, 0), detection_row([300, 300, 20, 20], 0.6, 1)])
+
+    expected = Nx.concatenate([large, small], axis: 1) |> YoloFastNMS.run([]) |> round_results()
+
+    assert [
+             [100, 100, 40, 40, 0.9, 0],
+             [300, 300, 20, 20, 0.6, 1],
+             [400, 400, 80, 80, 0.4, 2]
+           ] == expected
+
+    assert expected == [large, small] |> YoloFastNMS.run_scales() |> round_results()
+
+    # the row indexes of the concatenation
+    assert [0, 3, 1] ==
+             [large, small]
+             |> YoloFastNMS.run_scales(format: :map, row_index: true)
+             |> Enum.map(& &1.row_index)
+
+    assert_raise ArgumentError, ~r/different number of features/, fn ->
+      YoloFastNMS.run_scales([large, Nx.slice_along_axis(small, 0, 50, axis: 0)])
+    end
+  end
+
+  test "palette_size adds the class modulo the palette size as color_index" do
+    model_output =
+      [
+        detection_row([100, 100, 10, 10], 0.9, 3),
+        detection_row([200, 200, 10, 10], 0.8, 12),
+        detection_row([300, 300, 10, 10], 0.7, 17)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [{3, 3}, {12, 2}, {17, 2}] ==
+             model_output
+             |> YoloFastNMS.run(format: :map, palette_size: 5)
+             |> Enum.map(&{&1.class_idx, &1.color_index})
+             |> Enum.sort()
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, palette_size: 5) end
+
+    assert_raise ArgumentError, ~r/palette_size must be a positive integer/, fn ->
+      YoloFastNMS.run(model_output, format: :map, palette_size: 0)
+    end
+  end
+
+  test "sort: :raster orders the detections by cy then cx" do
+    model_output =
+      [
+        detection_row([300, 200, 10, 10], 0.9, 0),
+        detection_row([100, 400, 10, 10], 0.8, 1),
+        detection_row([100, 200, 10, 10], 0.7, 2),
+        detection_row([500, 50, 10, 10], 0.6, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [
+             [500, 50, 10, 10, 0.6, 0],
+             [100, 200, 10, 10, 0.7, 2],
+             [300, 200, 10, 10, 0.9, 0],
+             [100, 400, 10, 10, 0.8, 1]
+           ] == model_output |> YoloFastNMS.run(sort: :raster) |> round_results()
+  end
+
+  test "calibration remaps the prob through a piecewise-linear curve" do
+    model_output =
+      [
+        detection_row([10, 10, 10, 20], 0.3, 0),
+        detection_row([100, 100, 10, 20], 0.7, 1),
+        detection_row([200, 200, 10, 20], 0.05, 2),
+        detection_row([300, 300, 10, 20], 0.95, 3)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    # two segments, the scores out of [0.1, 0.9] take the values of the ends
+    calibration = [{0.1, 0.2}, {0.5, 0.4}, {0.9, 1.0}]
+
+    assert [
+             [10, 10, 10, 20, 0.3, 0],
+             [100, 100, 10, 20, 0.7, 1],
+             [200, 200, 10, 20, 0.2, 2],
+             [300, 300, 10, 20, 1.0, 3]
+           ] ==
+             model_output
+             |> YoloFastNMS.run(prob_threshold: 0.1, calibration: calibration)
+             |> round_results()
+             |> Enum.sort_by(&List.last/1)
+
+    # the threshold applies to the calibrated prob
+    assert [1, 3] ==
+             model_output
+             |> YoloFastNMS.run(prob_threshold: 0.5, calibration: calibration)
+             |> Enum.map(&trunc(List.last(&1)))
+             |> Enum.sort()
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, calibration: [{0.5, 0.1}, {0.2, 0.3}])
+    end
+  end
+
+  test "calibration drops the NaN scores instead of calibrating them" do
+    # [cx, cy, w, h, objectness, class 0, class 1], the NaN objectness makes a NaN score
+    model_output =
+      Nx.tensor([[0, 0, 10, 20, :nan, 0.8, 0.1], [50, 50, 10, 20, 0.9, 0.1, 0.8]], type: {:f, 32})
+
+    assert [[50, 50, 10, 20, 1.0, 1]] ==
+             model_output
+             |> YoloFastNMS.run(
+               transpose: false,
+               objectness: true,
+               calibration: [{0.1, 0.2}, {0.5, 1.0}],
+               min_results: 2
+             )
+             |> round_results()
+  end
+
+  test "grid_max keeps only the most probable candidate of each class in each cell" do
+    model_output =
+      [
+        # same 32x32 cell, no overlap
+        detection_row([4, 4, 6, 6], 0.6, 0),
+        detection_row([28, 28, 6, 6], 0.9, 0),
+        # same cell, another class
+        detection_row([16, 16, 6, 6], 0.5, 1),
+        # next cell
+        detection_row([40, 4, 6, 6], 0.7, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [
+             [4, 4, 6, 6, 0.6, 0],
+             [16, 16, 6, 6, 0.5, 1],
+             [28, 28, 6, 6, 0.9, 0],
+             [40, 4, 6, 6, 0.7, 0]
+           ] == model_output |> YoloFastNMS.run([]) |> round_results() |> Enum.sort()
+
+    assert [[16, 16, 6, 6, 0.5, 1], [28, 28, 6, 6, 0.9, 0], [40, 4, 6, 6, 0.7, 0]] ==
+             model_output |> YoloFastNMS.run(grid_max: 32) |> round_results() |> Enum.sort()
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, grid_max: 0) end
+  end
+
+  test "class_scores returns the scores of the requested classes of each kept box" do
+    model_output =
+      [
+        [10, 10, 10, 20, 0.1, 0.8, 0.3],
+        [100, 100, 10, 20, 0.6, 0.2, 0.0]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    detections =
+      model_output
+      |> YoloFastNMS.run(format: :map, class_scores: [0, 2])
+      |> Enum.sort_by(& &1.class_idx)
+
+    assert [%{class_idx: 0, class_scores: scores_0}, %{class_idx: 1, class_scores: scores_1}] =
+             detections
+
+    assert [0, 2] == scores_0 |> Map.keys() |> Enum.sort()
+    assert_in_delta scores_0[0], 0.6, 1.0e-6
+    assert_in_delta scores_0[2], 0.0, 1.0e-6
+    assert_in_delta scores_1[0], 0.1, 1.0e-6
+    assert_in_delta scores_1[2], 0.3, 1.0e-6
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, class_scores: [0]) end
+  end
+
+  test "oversize_policy keeps, clamps or drops the boxes larger than the image" do
+    model_output =
+      [
+        detection_row([50, 50, 150, 40], 0.9, 0),
+        detection_row([20, 20, 10, 10], 0.8, 1)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    run = fn policy ->
+      model_output
+      |> YoloFastNMS.run(image_size: {100, 80}, oversize_policy: policy)
+      |> round_results()
+      |> Enum.sort_by(&List.last/1)
+    end
+
+    assert [[50, 50, 150, 40, 0.9, 0], [20, 20, 10, 10, 0.8, 1]] == run.(:keep)
+    assert [[50, 50, 100, 40, 0.9, 0], [20, 20, 10, 10, 0.8, 1]] == run.(:clamp)
+    assert [[20, 20, 10, 10, 0.8, 1]] == run.(:drop)
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, oversize_policy: :drop) end
+  end
+
+  test "byte_offset reads the tensor embedded after a header" do
+    payload =
+      [
+        detection_row([10, 10, 10, 20], 0.9, 1),
+        detection_row([200, 200, 10, 20], 0.7, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+      |> Nx.to_binary()
+
+    header = "FRAME:0001"
+    framed = header <> payload <> "END"
+    {rows, columns} = {84, 2}
+
+    assert [[10, 10, 10, 20, 0.9, 1], [200, 200, 10, 20, 0.7, 0]] ==
+             framed
+             |> YoloFastNMS.run_with_binary(0.5, 0.5, rows, columns, true,
+               byte_offset: byte_size(header)
+             )
+             |> round_results()
+             |> Enum.sort()
+
+    # the tensor must be within the binary
+    assert_raise ErlangError, fn ->
+      YoloFastNMS.run_with_binary(framed, 0.5, 0.5, rows, columns, true, byte_offset: 20)
+    end
+  end
+
+  test "top_k_classes returns the most probable classes of each kept box" do
+    model_output =
+      [[10, 10, 10, 20, 0.1, 0.5, 0.05, 0.7, 0.3]]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [%{class_idx: 3, top_classes: top_classes}] =
+             YoloFastNMS.run(model_output, format: :map, top_k_classes: 3)
+
+    assert [3, 1, 4] == Enum.map(top_classes, &elem(&1, 0))
+
+    Enum.zip_with(top_classes, [0.7, 0.5, 0.3], fn {_class, prob}, expected ->
+      assert_in_delta prob, expected, 1.0e-6
+    end)
+
+    # k = 1 keeps the argmax only
+    assert [detection] = YoloFastNMS.run(model_output, format: :map)
+    refute Map.has_key?(detection, :top_classes)
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, top_k_classes: 3) end
+  end
+
+  test "min_support drops the kept boxes without overlapping candidates" do
+    model_output =
+      [
+        # a cluster of candidates around the same object
+        detection_row([100, 100, 40, 40], 0.9, 0),
+        detection_row([102, 101, 40, 40], 0.8, 0),
+        detection_row([98, 99, 40, 40], 0.7, 0),
+        # an isolated confident box
+        detection_row([300, 300, 40, 40], 0.95, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [[100, 100, 40, 40, 0.9, 0], [300, 300, 40, 40, 0.95, 0]] ==
+             model_output |> YoloFastNMS.run([]) |> round_results() |> Enum.sort()
+
+    assert [[100, 100, 40, 40, 0.9, 0]] ==
+             model_output |> YoloFastNMS.run(min_support: 2) |> round_results()
+
+    # a higher support_iou than the overlap of the cluster
+    assert [] == YoloFastNMS.run(model_output, min_support: 2, support_iou: 0.95)
+  end
+
+  test "format: :yolo_label returns the class first and the normalized coordinates" do
+    model_output =
+      [detection_row([320, 160, 64, 32], 0.9, 7)]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [[7, cx, cy, w, h]] =
+             YoloFastNMS.run(model_output, format: :yolo_label, image_size: {640, 320})
+
+    assert_in_delta cx, 0.5, 1.0e-6
+    assert_in_delta cy, 0.5, 1.0e-6
+    assert_in_delta w, 0.1, 1.0e-6
+    assert_in_delta h, 0.1, 1.0e-6
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, format: :yolo_label) end
+  end
+
+  test "match_tolerance is the same box of dedup and fingerprint" do
+    near_equal = [
+      detection_row([100, 100, 40, 40], 0.9, 0),
+      detection_row([101, 99, 41, 40], 0.8, 0)
+    ]
+
+    different = [
+      detection_row([100, 100, 40, 40], 0.9, 0),
+      detection_row([110, 100, 40, 40], 0.8, 0)
+    ]
+
+    dedup = fn rows ->
+      rows
+      |> Nx.tensor(type: {:f, 32})
+      |> YoloFastNMS.run(transpose: false, iou_threshold: 1.0, dedup: true, match_tolerance: 2)
+      |> round_results()
+      |> Enum.sort()
+    end
+
+    assert [[100, 100, 40, 40, 0.9, 0]] == dedup.(near_equal)
+    assert [[100, 100, 40, 40, 0.9, 0], [110, 100, 40, 40, 0.8, 0]] == dedup.(different)
+
+    fingerprint = fn row, options ->
+      {_detections, metadata} =
+        [row]
+        |> Nx.tensor(type: {:f, 32})
+        |> YoloFastNMS.run([transpose: false, metadata: true, fingerprint: true] ++ options)
+
+      metadata.fingerprint
+    end
+
+    moved = detection_row([101, 99, 41, 40], 0.9, 0)
+    [original, _] = near_equal
+
+    assert fingerprint.(original, match_tolerance: 4) == fingerprint.(moved, match_tolerance: 4)
+    assert fingerprint.(original, []) != fingerprint.(moved, [])
+
+    assert fingerprint.(original, match_tolerance: 4) !=
+             fingerprint.(detection_row([110, 100, 40, 40], 0.9, 0), match_tolerance: 4)
+  end
+
+  test "valid_columns ignores the padding columns of the rows" do
+    rows = [
+      detection_row([10, 10, 10, 20], 0.9, 1, 80),
+      detection_row([200, 200, 10, 20], 0.7, 79, 80)
+    ]
+
+    unpadded = rows |> Nx.tensor(type: {:f, 32}) |> Nx.transpose(axes: [1, 0])
+
+    # 84 features padded to 96, with garbage in the padding to be sure it's not read
+    padded =
+      rows
+      |> Enum.map(&(&1 ++ List.duplicate(0.99, 12)))
+      |> Nx.tensor(type: {:f, 32})
+
+    expected = unpadded |> YoloFastNMS.run([]) |> round_results() |> Enum.sort()
+    assert [[10, 10, 10, 20, 0.9, 1], [200, 200, 10, 20, 0.7, 79]] == expected
+
+    assert expected ==
+             padded
+             |> Nx.transpose(axes: [1, 0])
+             |> YoloFastNMS.run(valid_columns: 84)
+             |> round_results()
+             |> Enum.sort()
+
+    assert expected ==
+             padded
+             |> YoloFastNMS.run(transpose: false, valid_columns: 84)
+             |> round_results()
+             |> Enum.sort()
+
+    assert_raise ErlangError, fn -> YoloFastNMS.run(unpadded, valid_columns: 90) end
+  end
+
+  test "min_center_distance keeps the most probable of the close boxes across classes" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.8, 0),
+        detection_row([108, 106, 40, 40], 0.9, 1),
+        detection_row([300, 300, 40, 40], 0.7, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    # different classes, the per-class NMS keeps both close boxes
+    assert 3 == model_output |> YoloFastNMS.run([]) |> length()
+
+    assert [[108, 106, 40, 40, 0.9, 1], [300, 300, 40, 40, 0.7, 2]] ==
+             model_output
+             |> YoloFastNMS.run(min_center_distance: 20)
+             |> round_results()
+             |> Enum.sort()
+
+    # 10 pixels apart, far enough
+    assert 3 == model_output |> YoloFastNMS.run(min_center_distance: 10) |> length()
+  end
+
+  test "sorted_classes returns the classes in ascending order" do
+    model_output =
+      [
+        detection_row([10, 10, 10, 20], 0.9, 5),
+        detection_row([100, 100, 10, 20], 0.6, 2),
+        detection_row([200, 200, 10, 20], 0.8, 9),
+        detection_row([300, 300, 10, 20], 0.7, 0),
+        detection_row([400, 400, 10, 20], 0.95, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [
+             [300, 300, 10, 20, 0.7, 0],
+             [400, 400, 10, 20, 0.95, 2],
+             [100, 100, 10, 20, 0.6, 2],
+             [10, 10, 10, 20, 0.9, 5],
+             [200, 200, 10, 20, 0.8, 9]
+           ] == model_output |> YoloFastNMS.run([]) |> round_results()
+  end
+
+  test "force_keep_threshold keeps the overlapping high-confidence boxes" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.95, 0),
+        detection_row([104, 102, 40, 40], 0.92, 0),
+        detection_row([98, 101, 40, 40], 0.6, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [[100, 100, 40, 40, 0.95, 0]] == model_output |> YoloFastNMS.run([]) |> round_results()
+
+    assert [[100, 100, 40, 40, 0.95, 0], [104, 102, 40, 40, 0.92, 0]] ==
+             model_output
+             |> YoloFastNMS.run(force_keep_threshold: 0.9)
+             |> round_results()
+
+    # each merged detection is a candidate of its own
+    assert [[100, 100, 40, 40, 0.95, 0], [104, 102, 40, 40, 0.92, 0]] ==
+             [[100, 100, 40, 40, 0.95, 0]]
+             |> YoloFastNMS.merge([[104, 102, 40, 40, 0.92, 0]], force_keep_threshold: 0.9)
+             |> round_results()
+  end
+
+  test "band_low and band_high run the NMS only within the confidence band" do
+    model_output =
+      [
+        # above the band, overlapping
+        detection_row([100, 100, 40, 40], 0.95, 0),
+        detection_row([104, 102, 40, 40], 0.92, 0),
+        # within the band, overlapping
+        detection_row([300, 300, 40, 40], 0.6, 0),
+        detection_row([302, 301, 40, 40], 0.5, 0),
+        # below the band
+        detection_row([500, 500, 40, 40], 0.35, 0)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [
+             [100, 100, 40, 40, 0.95, 0],
+             [300, 300, 40, 40, 0.6, 0],
+             [500, 500, 40, 40, 0.35, 0]
+           ] == model_output |> YoloFastNMS.run([]) |> round_results() |> Enum.sort()
+
+    assert [
+             [100, 100, 40, 40, 0.95, 0],
+             [104, 102, 40, 40, 0.92, 0],
+             [300, 300, 40, 40, 0.6, 0]
+           ] ==
+             model_output
+             |> YoloFastNMS.run(band_low: 0.4, band_high: 0.9)
+             |> round_results()
+             |> Enum.sort()
+
+    assert_raise ArgumentError, ~r/band_low must not be greater than band_high/, fn ->
+      YoloFastNMS.run(model_output, band_low: 0.9, band_high: 0.4)
+    end
+  end
+
+  test "iou_eps stabilizes the IoU of the tiny boxes" do
+    # 2x2 pixels boxes, 1 pixel apart: intersection 2, union 6
+    assert_in_delta 1 / 3, YoloFastNMS.iou([10, 10, 2, 2], [11, 10, 2, 2]), 1.0e-6
+    assert_in_delta 2 / 7, YoloFastNMS.iou([10, 10, 2, 2], [11, 10, 2, 2], iou_eps: 1), 1.0e-6
+
+    # the same tiny box is bounded below 1
+    assert 1.0 == YoloFastNMS.iou([10, 10, 2, 2], [10, 10, 2, 2])
+    assert_in_delta 0.8, YoloFastNMS.iou([10, 10, 2, 2], [10, 10, 2, 2], iou_eps: 1), 1.0e-6
+
+    # the large boxes are barely changed
+    assert_in_delta YoloFastNMS.iou([100, 100, 40, 40], [110, 100, 40, 40]),
+                    YoloFastNMS.iou([100, 100, 40, 40], [110, 100, 40, 40], iou_eps: 1.0e-3),
+                    1.0e-6
+  end
+
+  test "zero_union_iou: :one suppresses the identical zero-area boxes" do
+    model_output =
+      [
+        detection_row([100, 100, 0, 0], 0.9, 0, 2),
+        detection_row([100, 100, 0, 0], 0.8, 0, 2),
+        # zero-area too, at another place
+        detection_row([200, 100, 0, 0], 0.7, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert 3 == model_output |> YoloFastNMS.run(transpose: false) |> length()
+
+    assert 3 ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, zero_union_iou: :zero)
+             |> length()
+
+    assert [[100, 100, 0, 0, 0.9, 0], [200, 100, 0, 0, 0.7, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, zero_union_iou: :one)
+             |> round_results()
+
+    assert 0.0 == YoloFastNMS.iou([100, 100, 0, 0], [100, 100, 0, 0])
+    assert 0.0 == YoloFastNMS.iou([100, 100, 0, 0], [100, 100, 0, 0], zero_union_iou: :zero)
+    assert 1.0 == YoloFastNMS.iou([100, 100, 0, 0], [100, 100, 0, 0], zero_union_iou: :one)
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, zero_union_iou: :half)
+    end
+  end
+
+  test "objectness_classes gates only the classes in the ranges" do
+    # objectness 0.5, class 0 gated, class 2 ungated
+    model_output = Nx.tensor([[100, 100, 40, 40, 0.5, 0.8, 0.0, 0.6]], type: {:f, 32})
+    options = [transpose: false, objectness: true, multi_label: true]
+
+    assert MapSet.new([[100, 100, 40, 40, 0.4, 0], [100, 100, 40, 40, 0.3, 2]]) ==
+             model_output |> YoloFastNMS.run(options) |> round_results() |> MapSet.new()
+
+    assert MapSet.new([[100, 100, 40, 40, 0.4, 0], [100, 100, 40, 40, 0.6, 2]]) ==
+             model_output
+             |> YoloFastNMS.run([objectness_classes: [0..1]] ++ options)
+             |> round_results()
+             |> MapSet.new()
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, objectness_classes: [0..1], transpose: false)
+    end
+  end
+
+  test "prior_grid weights the detections by the region of their center" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        # right half of the image, suppressed by the grid
+        detection_row([500, 100, 40, 40], 0.8, 0, 2),
+        # out of the image, weight 1.0
+        detection_row([700, 100, 40, 40], 0.7, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    grid = Nx.tensor([[0.5, 0.0]])
+
+    assert [[100, 100, 40, 40, 0.45, 0], [700, 100, 40, 40, 0.7, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, prior_grid: grid, image_size: {640, 640})
+             |> round_results()
+             |> Enum.sort()
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, prior_grid: grid)
+    end
+  end
+
+  test "detections with non-finite coordinates are dropped" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        detection_row([300, 100, :infinity, 40], 0.8, 0, 2),
+        detection_row([:nan, 100, 40, 40], 0.8, 1, 2),
+        detection_row([500, :neg_infinity, 40, 40], 0.8, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [[100, 100, 40, 40, 0.9, 0]] ==
+             model_output |> YoloFastNMS.run(transpose: false) |> round_results()
+  end
+
+  test "score_column and class_column read the precomputed score and class" do
+    # [cx, cy, w, h, ignored, class_idx, ignored, ignored, score]
+    model_output =
+      Nx.tensor(
+        [
+          [100, 100, 40, 40, 0.99, 2, 0.5, 0.0, 0.9],
+          [104, 102, 40, 40, 0.99, 2, 0.5, 0.0, 0.7],
+          [300, 300, 20, 20, 0.0, 7, 0.99, 0.0, 0.6],
+          [500, 500, 20, 20, 0.0, 1, 0.99, 0.0, 0.1]
+        ],
+        type: {:f, 32}
+      )
+
+    assert [[100, 100, 40, 40, 0.9, 2], [300, 300, 20, 20, 0.6, 7]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, score_column: 8, class_column: 5)
+             |> round_results()
+             |> Enum.sort()
+
+    assert_raise ErlangError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, score_column: 9, class_column: 5)
+    end
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, score_column: 8)
+    end
+  end
+
+  test "argmaxed rows with the class id as a float" do
+    model_output =
+      [
+        [100, 100, 40, 40, 0.9, 11.0],
+        [300, 100, 40, 40, 0.8, 2.9999],
+        # invalid class ids, skipped
+        [500, 100, 40, 40, 0.7, -1.0],
+        [700, 100, 40, 40, 0.7, 1.0e9]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [[100, 100, 40, 40, 0.9, 11], [300, 100, 40, 40, 0.8, 3]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, argmaxed: true)
+             |> round_results()
+             |> Enum.sort()
+  end
+
+  test "point NMS suppresses by the distance of the centers" do
+    model_output =
+      [
+        detection_row([100, 100, 4, 4], 0.9, 0, 2),
+        # center 5 pixels away, boxes not overlapping
+        detection_row([105, 100, 4, 4], 0.8, 0, 2),
+        detection_row([300, 100, 4, 4], 0.7, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert 3 == model_output |> YoloFastNMS.run(transpose: false) |> length()
+
+    assert [[100, 100, 4, 4, 0.9, 0], [300, 100, 4, 4, 0.7, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, method: :point, radius: 8)
+             |> round_results()
+
+    assert 3 ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, method: :point, radius: 4)
+             |> length()
+  end
+
+  test "class_sigmas decays the classes differently" do
+    # IoU 0.6 between the boxes of each class
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        detection_row([110, 100, 40, 40], 0.8, 0, 2),
+        detection_row([400, 100, 40, 40], 0.9, 1, 2),
+        detection_row([410, 100, 40, 40], 0.8, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    options = [transpose: false, method: :soft, sigma: 0.5]
+
+    # exp(-0.36 / 0.5) keeps the decayed boxes above prob_threshold
+    assert MapSet.new([
+             [100, 100, 40, 40, 0.9, 0],
+             [110, 100, 40, 40, 0.39, 0],
+             [400, 100, 40, 40, 0.9, 1],
+             [410, 100, 40, 40, 0.39, 1]
+           ]) == model_output |> YoloFastNMS.run(options) |> round_results() |> MapSet.new()
+
+    # exp(-0.36 / 0.1) drops the decayed box of class 1
+    assert MapSet.new([
+             [100, 100, 40, 40, 0.9, 0],
+             [110, 100, 40, 40, 0.39, 0],
+             [400, 100, 40, 40, 0.9, 1]
+           ]) ==
+             model_output
+             |> YoloFastNMS.run([class_sigmas: %{1 => 0.1}] ++ options)
+             |> round_results()
+             |> MapSet.new()
+
+    # the integer sigmas are accepted, exp(-0.36 / 1) for both classes
+    assert [0.56, 0.56] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, method: :soft, sigma: 1, class_sigmas: %{1 => 1})
+             |> round_results()
+             |> Enum.map(&Enum.at(&1, 4))
+             |> Enum.filter(&(&1 < 0.9))
+
+    for invalid <- [[sigma: 0], [sigma: -1], [class_sigmas: %{1 => 0}]] do
+      assert_raise ArgumentError, fn ->
+        YoloFastNMS.run(model_output, Keyword.merge(options, invalid))
+      end
+    end
+  end
+
+  test "union geometry grows the kept box over the suppressed ones" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        # IoU 0.6 with the first box, suppressed
+        detection_row([110, 100, 40, 40], 0.8, 0, 2),
+        # far away, kept as it is
+        detection_row([400, 400, 20, 20], 0.7, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [[100, 100, 40, 40, 0.9, 0], [400, 400, 20, 20, 0.7, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false)
+             |> round_results()
+
+    assert [[105, 100, 50, 40, 0.9, 0], [400, 400, 20, 20, 0.7, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, geometry: :union)
+             |> round_results()
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, geometry: :union, method: :soft)
+    end
+  end
+
+  test "dedup collapses exact duplicated rows keeping the most probable" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.6, 0, 6),
+        detection_row([0, 0, 10, 20], 0.8, 0, 6),
+        detection_row([0, 0, 10, 20], 0.7, 0, 6),
+        # same geometry, different class: not a duplicate
+        detection_row([0, 0, 10, 20], 0.5, 1, 6),
+        detection_row([1, 0, 10, 20], 0.5, 0, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    # with iou_threshold 1.0 NMS keeps the identical boxes
+    assert 5 ==
+             model_output
+             |> YoloFastNMS.run(iou_threshold: 1.0, transpose: false)
+             |> length()
+
+    assert MapSet.new([
+             [0, 0, 10, 20, 0.8, 0],
+             [0, 0, 10, 20, 0.5, 1],
+             [1, 0, 10, 20, 0.5, 0]
+           ]) ==
+             model_output
+             |> YoloFastNMS.run(iou_threshold: 1.0, transpose: false, dedup: true)
+             |> round_results()
+             |> MapSet.new()
+
+    assert MapSet.new([
+             [0, 0, 10, 20, 0.8, 0],
+             [0, 0, 10, 20, 0.5, 1]
+           ]) ==
+             model_output
+             |> YoloFastNMS.run(
+               iou_threshold: 1.0,
+               transpose: false,
+               dedup: true,
+               dedup_tolerance: 1.0
+             )
+             |> round_results()
+             |> MapSet.new()
+
+    # a tolerance in pixels, as an integer
+    assert 2 ==
+             model_output
+             |> YoloFastNMS.run(iou_threshold: 1.0, transpose: false, dedup: true, dedup_tolerance: 1)
+             |> length()
+
+    # the exact dedup keeps the tensor order, the first of the tied boxes is kept
+    tied =
+      [detection_row([0, 0, 10, 20], 0.5, 0, 6), detection_row([1, 0, 10, 20], 0.5, 0, 6)]
+      |> Nx.tensor(type: {:f, 32})
+
+    for _ <- 1..20 do
+      assert [[0, 0, 10, 20, 0.5, 0]] ==
+               tied |> YoloFastNMS.run(transpose: false, dedup: true) |> round_results()
+    end
+  end
+
+  test "single-class output matches the multi-class path" do
+    bboxes = [
+      {[0, 0, 10, 20], 0.6},
+      {[1, 0, 10, 20], 0.8},
+      {[40, 40, 10, 20], 0.5},
+      {[41, 41, 10, 20], 0.3}
+    ]
+
+    single_class =
+      bboxes
+      |> Enum.map(fn {bbox, prob} -> detection_row(bbox, prob, 0, 1) end)
+      |> Nx.tensor(type: {:f, 32})
+
+    # same boxes, plus a far away box of another class to force the per-class grouping
+    multi_class =
+      bboxes
+      |> Enum.map(fn {bbox, prob} -> detection_row(bbox, prob, 0, 2) end)
+      |> Kernel.++([detection_row([500, 500, 10, 20], 0.9, 1, 2)])
+      |> Nx.tensor(type: {:f, 32})
+
+    expected = MapSet.new([[1, 0, 10, 20, 0.8, 0], [40, 40, 10, 20, 0.5, 0]])
+
+    assert expected ==
+             single_class
+             |> YoloFastNMS.run(transpose: false)
+             |> round_results()
+             |> MapSet.new()
+
+    assert MapSet.put(expected, [500, 500, 10, 20, 0.9, 1]) ==
+             multi_class
+             |> YoloFastNMS.run(transpose: false)
+             |> round_results()
+             |> MapSet.new()
+  end
+
+  test "prob_threshold boundaries and invalid thresholds" do
+    model_output =
+      [
+        detection_row([10, 10, 20, 20], 1.0, 0, 2),
+        detection_row([100, 100, 20, 20], 0.99, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [[10, 10, 20, 20, 1.0, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, prob_threshold: 1.0)
+             |> round_results()
+
+    assert [] == YoloFastNMS.run(model_output, transpose: false, prob_threshold: 1.5)
+
+    assert %{} ==
+             YoloFastNMS.run(model_output,
+               transpose: false,
+               prob_threshold: 1.5,
+               group_by_class: true
+             )
+
+    # the rows are still checked above 1.0
+    malformed =
+      [detection_row([:nan, 10, 20, 20], 0.9, 0, 2), detection_row([:nan, 10, 20, 20], 0.9, 0, 2)]
+      |> Nx.tensor(type: {:f, 32})
+
+    options = [transpose: false, prob_threshold: 1.5]
+
+    assert_raise ErlangError, ~r/2 malformed rows/, fn ->
+      YoloFastNMS.run(malformed, [parse_mode: :strict] ++ options)
+    end
+
+    assert {[], %{dropped_rows: 2, duplicate_rows: 1}} =
+             YoloFastNMS.run(malformed, [metadata: true, duplicate_rows: true] ++ options)
+
+    for options <- [
+          [prob_threshold: -0.1],
+          [prob_threshold: nil],
+          [iou_threshold: -0.1],
+          [iou_threshold: 1.5]
+        ] do
+      assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, options) end
+    end
+  end
+
+  test "iou_threshold boundaries" do
+    model_output =
+      [
+        detection_row([10, 10, 20, 20], 0.9, 0, 2),
+        # IoU 0.5 with the first box
+        detection_row([10, 20, 20, 40], 0.8, 0, 2),
+        # doesn't overlap any box
+        detection_row([100, 100, 20, 20], 0.7, 0, 2),
+        detection_row([10, 10, 20, 20], 0.6, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    run = fn iou_threshold ->
+      model_output
+      |> YoloFastNMS.run(transpose: false, iou_threshold: iou_threshold)
+      |> round_results()
+      |> MapSet.new()
+    end
+
+    # an overlap equal to the threshold doesn't suppress
+    assert MapSet.new([
+             [10, 10, 20, 20, 0.9, 0],
+             [10, 20, 20, 40, 0.8, 0],
+             [100, 100, 20, 20, 0.7, 0],
+             [10, 10, 20, 20, 0.6, 1]
+           ]) == run.(0.5)
+
+    assert MapSet.new([
+             [10, 10, 20, 20, 0.9, 0],
+             [100, 100, 20, 20, 0.7, 0],
+             [10, 10, 20, 20, 0.6, 1]
+           ]) == run.(0.01)
+
+    # only the best box of each class
+    assert MapSet.new([[10, 10, 20, 20, 0.9, 0], [10, 10, 20, 20, 0.6, 1]]) == run.(0.0)
+  end
+
+  test "tie_prefer picks the box kept between equally probable boxes" do
+    model_output =
+      [
+        detection_row([10, 10, 20, 20], 0.8, 0, 2),
+        detection_row([10, 10, 22, 22], 0.8, 0, 2),
+        detection_row([10, 10, 18, 18], 0.8, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    for {tie_prefer, expected} <- [
+          first: [[10, 10, 20, 20, 0.8, 0]],
+          larger_area: [[10, 10, 22, 22, 0.8, 0]],
+          smaller_area: [[10, 10, 18, 18, 0.8, 0]]
+        ] do
+      assert expected ==
+               model_output
+               |> YoloFastNMS.run(transpose: false, tie_prefer: tie_prefer)
+               |> round_results()
+    end
+  end
+
+  test "class priorities order the agnostic suppression" do
+    model_output =
+      [
+        # backpack
+        detection_row([0, 0, 10, 20], 0.7, 2, 6),
+        # person, overlapping the backpack with a lower prob
+        detection_row([1, 0, 10, 20], 0.6, 0, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [[0, 0, 10, 20, 0.7, 2]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, agnostic: true)
+             |> round_results()
+
+    assert [[1, 0, 10, 20, 0.6, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, agnostic: true, class_priorities: %{0 => 1.5})
+             |> round_results()
+
+    # an integer weight
+    assert [[1, 0, 10, 20, 0.6, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, agnostic: true, class_priorities: %{0 => 2})
+             |> round_results()
+
+    for weight <- [0, -1.5] do
+      assert_raise ArgumentError, fn ->
+        YoloFastNMS.run(model_output, transpose: false, class_priorities: %{0 => weight})
+      end
+    end
+  end
+
+  test "min_results backfills with the most probable boxes below the threshold" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.2, 0, 6),
+        # overlaps the first box, skipped
+        detection_row([1, 0, 10, 20], 0.15, 0, 6),
+        detection_row([50, 50, 10, 20], 0.1, 1, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [] == YoloFastNMS.run(model_output, prob_threshold: 0.5, transpose: false)
+
+    assert [[0, 0, 10, 20, 0.2, 0]] ==
+             model_output
+             |> YoloFastNMS.run(prob_threshold: 0.5, transpose: false, min_results: 1)
+             |> round_results()
+
+    assert [[0, 0, 10, 20, 0.2, 0], [50, 50, 10, 20, 0.1, 1]] ==
+             model_output
+             |> YoloFastNMS.run(prob_threshold: 0.5, transpose: false, min_results: 2)
+             |> round_results()
+  end
+
+  test "min_results suppresses the backfilled boxes like the NMS" do
+    model_output =
+      [
+        detection_row([100, 100, 20, 20], 0.9, 0) ++ [0],
+        # overlaps the kept box of another group, backfilled
+        detection_row([102, 100, 20, 20], 0.3, 0) ++ [1],
+        # doesn't overlap, suppressed by the zero iou_threshold
+        detection_row([300, 300, 20, 20], 0.2, 0) ++ [0]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert [%{group: 0, prob: 0.9}, %{group: 1, prob: 0.3}] =
+             model_output
+             |> YoloFastNMS.run(
+               group_column: true,
+               format: :map,
+               iou_threshold: 0.0,
+               min_results: 3
+             )
+             |> Enum.map(&%{&1 | prob: Float.round(&1.prob, 2)})
+             |> Enum.sort_by(& &1.group)
+  end
+
+  test "min_results skips the NaN scores" do
+    # [cx, cy, w, h, objectness, class 0, class 1], the NaN objectness makes a NaN score
+    model_output =
+      Nx.tensor([[0, 0, 10, 20, :nan, 0.8, 0.1], [50, 50, 10, 20, 0.5, 0.1, 0.2]], type: {:f, 32})
+
+    assert [[50, 50, 10, 20, 0.1, 1]] ==
+             model_output
+             |> YoloFastNMS.run(
+               prob_threshold: 0.5,
+               transpose: false,
+               objectness: true,
+               min_results: 2
+             )
+             |> round_results()
+  end
+
+  test "objectness fused with the class probability" do
+    # [cx, cy, w, h, objectness, class 0, class 1]
+    model_output = Nx.tensor([[10, 10, 4, 4, 0.5, 0.1, 0.8]], type: {:f, 32})
+
+    for {score_combine, expected_prob} <- [
+          {:product, 0.4},
+          {:geometric_mean, :math.sqrt(0.4)},
+          {{:weighted, 0.25}, 0.725}
+        ] do
+      assert [[10.0, 10.0, 4.0, 4.0, prob, 1.0]] =
+               YoloFastNMS.run(model_output,
+                 transpose: false,
+                 objectness: true,
+                 score_combine: score_combine
+               )
+
+      assert_in_delta expected_prob, prob, 1.0e-6
+    end
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, objectness: true, score_combine: {:weighted, 2})
+    end
+  end
+
+  test "margin filter with softmax and sigmoid scores" do
+    model_output =
+      [
+        # ambiguous between class 0 and 1
+        [0, 0, 10, 20, 0.45, 0.4, 0.15],
+        [50, 50, 10, 20, 0.7, 0.2, 0.1]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    for score_type <- [:softmax, :sigmoid] do
+      assert [[50, 50, 10, 20, 0.7, 0]] ==
+               model_output
+               |> YoloFastNMS.run(transpose: false, score_type: score_type, margin_threshold: 0.1)
+               |> round_results()
+    end
+
+    # the integer margins, none of the candidates has a margin of 1
+    assert 2 == model_output |> YoloFastNMS.run(transpose: false, margin_threshold: 0) |> length()
+    assert [] == YoloFastNMS.run(model_output, transpose: false, margin_threshold: 1)
+
+    sigmoid_output =
+      [
+        # both classes are present
+        [0, 0, 10, 20, 0.9, 0.85, 0.1]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [] ==
+             YoloFastNMS.run(sigmoid_output, transpose: false, margin_threshold: 0.1)
+
+    # with multi-label each high class is a candidate and the margin doesn't apply
+    assert MapSet.new([[0, 0, 10, 20, 0.9, 0], [0, 0, 10, 20, 0.85, 1]]) ==
+             sigmoid_output
+             |> YoloFastNMS.run(transpose: false, margin_threshold: 0.1, multi_label: true)
+             |> round_results()
+             |> MapSet.new()
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(sigmoid_output, transpose: false, score_type: :softmax, multi_label: true)
+    end
+  end
+
+  test "soft and matrix NMS decay the overlapping boxes" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.9, 0, 6),
+        # IoU ~0.82 with the first box
+        detection_row([1, 0, 10, 20], 0.8, 0, 6),
+        # IoU ~0.33 with the first box
+        detection_row([5, 0, 10, 20], 0.7, 0, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [{0.9, 0}] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, iou_threshold: 0.3)
+             |> Enum.map(fn [cx, _, _, _, prob, _] -> {Float.round(prob, 2), trunc(cx)} end)
+
+    for method <- [:soft, :matrix] do
+      # the box almost overlapping the first one decays below the threshold,
+      # the other one survives with a lower prob
+      assert [{0.9, 0}, {prob, 5}] =
+               model_output
+               |> YoloFastNMS.run(transpose: false, prob_threshold: 0.4, method: method)
+               |> Enum.map(fn [cx, _, _, _, prob, _] -> {Float.round(prob, 2), trunc(cx)} end)
+               |> Enum.sort(:desc)
+
+      assert prob < 0.7 and prob > 0.4
+    end
+  end
+
+  test "confluence keeps one box per cluster of close boxes" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.9, 0, 2),
+        # proximity ~0.18 with the first box
+        detection_row([1, 0, 10, 20], 0.8, 0, 2),
+        # far away, proximity ~1.67
+        detection_row([50, 0, 10, 20], 0.7, 0, 2),
+        detection_row([1, 0, 10, 20], 0.6, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert MapSet.new([
+             [0, 0, 10, 20, 0.9, 0],
+             [50, 0, 10, 20, 0.7, 0],
+             [1, 0, 10, 20, 0.6, 1]
+           ]) ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, method: :confluence)
+             |> round_results()
+             |> MapSet.new()
+
+    assert 4 ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, method: :confluence, proximity_threshold: 0.1)
+             |> length()
+
+    # an integer threshold, the three boxes of class 0 are a single cluster
+    assert 2 ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, method: :confluence, proximity_threshold: 2)
+             |> length()
+  end
+
+  test "detections grouped by class" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.7, 0, 6),
+        detection_row([1, 0, 10, 20], 0.6, 0, 6),
+        detection_row([50, 50, 10, 20], 0.6, 0, 6),
+        detection_row([0, 0, 10, 20], 0.5, 3, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert %{0 => class_0, 3 => class_3} =
+             YoloFastNMS.run(model_output, transpose: false, group_by_class: true)
+
+    assert MapSet.new([[0, 0, 10, 20, 0.7, 0], [50, 50, 10, 20, 0.6, 0]]) ==
+             class_0 |> round_results() |> MapSet.new()
+
+    assert [[0, 0, 10, 20, 0.5, 3]] == round_results(class_3)
+
+    assert %{} ==
+             YoloFastNMS.run(model_output,
+               prob_threshold: 0.9,
+               transpose: false,
+               group_by_class: true
+             )
+  end
+
+  test "map format with the class probabilities of the kept boxes" do
+    model_output =
+      [
+        [0, 0, 10, 20, 0.1, 0.7, 0.2],
+        # suppressed
+        [1, 0, 10, 20, 0.0, 0.6, 0.3]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [%{cx: 0.0, cy: 0.0, w: 10.0, h: 20.0, class_idx: 1, prob: prob}] =
+             YoloFastNMS.run(model_output, transpose: false, format: :map)
+
+    assert_in_delta prob, 0.7, 1.0e-6
+
+    assert [%{class_probs: class_probs}] =
+             YoloFastNMS.run(model_output, transpose: false, format: :map, class_probs: true)
+
+    assert [0.1, 0.7, 0.2] == Enum.map(class_probs, &Float.round(&1, 2))
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, class_probs: true)
+    end
+  end
+
+  test "suppressed boxes point to the kept box that suppressed them" do
+    model_output =
+      [
+        detection_row([400, 400, 20, 20], 0.95, 0, 2),
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        # IoU 0.6 with the second kept box
+        detection_row([110, 100, 40, 40], 0.8, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert {detections, %{suppressed: [suppressed]}} =
+             YoloFastNMS.run(model_output, transpose: false, metadata: true, suppressed: true)
+
+    assert [[400, 400, 20, 20, 0.95, 0], [100, 100, 40, 40, 0.9, 0]] == round_results(detections)
+    assert %{cx: 110.0, suppressed_by: 1} = suppressed
+    assert_in_delta 0.6, suppressed.iou, 1.0e-6
+
+    assert_raise ArgumentError, fn ->
+      YoloFastNMS.run(model_output, transpose: false, suppressed: true)
+    end
+  end
+
+  test "never_suppress classes bypass the suppression" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        # IoU 0.6 with the first box
+        detection_row([110, 100, 40, 40], 0.8, 0, 2),
+        detection_row([100, 100, 40, 40], 0.7, 1, 2),
+        # below prob_threshold
+        detection_row([100, 100, 40, 40], 0.1, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    options = [transpose: false, agnostic: true]
+
+    assert [[100, 100, 40, 40, 0.9, 0]] ==
+             model_output |> YoloFastNMS.run(options) |> round_results()
+
+    assert [[100, 100, 40, 40, 0.9, 0], [100, 100, 40, 40, 0.7, 1]] ==
+             model_output
+             |> YoloFastNMS.run([never_suppress: [1]] ++ options)
+             |> round_results()
+  end
+
+  test "relabel numbers the kept classes contiguously" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.9, 5, 6),
+        detection_row([100, 0, 10, 20], 0.8, 2, 6),
+        # not in keep_classes
+        detection_row([200, 0, 10, 20], 0.7, 3, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    options = [transpose: false, keep_classes: [5, 2]]
+
+    assert [[0, 0, 10, 20, 0.9, 5], [100, 0, 10, 20, 0.8, 2]] ==
+             model_output |> YoloFastNMS.run(options) |> round_results() |> Enum.sort()
+
+    assert {detections, %{class_mapping: %{0 => 5, 1 => 2}}} =
+             YoloFastNMS.run(model_output, [relabel: true, metadata: true] ++ options)
+
+    assert [[0, 0, 10, 20, 0.9, 0], [100, 0, 10, 20, 0.8, 1]] ==
+             detections |> round_results() |> Enum.sort()
+  end
+
+  test "fingerprint of the detections doesn't depend on their order" do
+    rows = [
+      detection_row([0, 0, 10, 20], 0.9, 0, 3),
+      detection_row([100, 0, 10, 20], 0.8, 1, 3),
+      detection_row([200, 0, 10, 20], 0.7, 2, 3)
+    ]
+
+    fingerprint = fn rows ->
+      {_detections, %{fingerprint: fingerprint}} =
+        rows
+        |> Nx.tensor(type: {:f, 32})
+        |> YoloFastNMS.run(transpose: false, metadata: true, fingerprint: true)
+
+      fingerprint
+    end
+
+    assert is_integer(fingerprint.(rows))
+    assert fingerprint.(rows) == fingerprint.(rows)
+    assert fingerprint.(rows) == fingerprint.(Enum.reverse(rows))
+    refute fingerprint.(rows) == fingerprint.(Enum.drop(rows, 1))
+  end
+
+  test "metadata with the per-class counts" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.9, 0, 3),
+        detection_row([1, 0, 10, 20], 0.8, 0, 3),
+        detection_row([50, 50, 10, 20], 0.7, 0, 3),
+        detection_row([200, 0, 10, 20], 0.6, 2, 3),
+        detection_row([0, 0, 10, 20], 0.1, 1, 3)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert {detections, %{class_counts: class_counts}} =
+             YoloFastNMS.run(model_output, transpose: false, metadata: true)
+
+    assert %{0 => 2, 2 => 1} == class_counts
+    assert class_counts ==
+             Enum.frequencies_by(detections, fn [_, _, _, _, _, class] -> trunc(class) end)
+  end
+
+  test "scaled output coordinates" do
+    model_output =
+      [
+        detection_row([100, 50, 20, 10], 0.8, 1, 2),
+        detection_row([101, 50, 20, 10], 0.6, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [[50, 25, 10, 5, 0.8, 1]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, scale: 0.5)
+             |> round_results()
+
+    assert [%{cx: 50.0, cy: 25.0, w: 10.0, h: 5.0, class_idx: 1}] =
+             YoloFastNMS.run(model_output, transpose: false, scale: 0.5, format: :map)
+
+    # an integer factor, like the offsets
+    assert [[200, 100, 40, 20, 0.8, 1]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, scale: 2)
+             |> round_results()
+
+    for scale <- [0, -1] do
+      assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, scale: scale) end
+    end
+  end
+
+  test "boxes format without prob and class" do
+    model_output =
+      [
+        detection_row([100, 50, 20, 10], 0.8, 1, 2),
+        detection_row([101, 50, 20, 10], 0.6, 1, 2),
+        detection_row([10, 10, 4, 4], 0.5, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert MapSet.new([[100.0, 50.0, 20.0, 10.0], [10.0, 10.0, 4.0, 4.0]]) ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, format: :boxes)
+             |> MapSet.new()
+  end
+
+  test "boxes clamped to the image flagged as clamped" do
+    model_output =
+      [
+        detection_row([100, 50, 20, 10], 0.8, 1, 2),
+        # crosses the left and the bottom borders of the 640x480 image
+        detection_row([4, 478, 20, 10], 0.6, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [
+             %{cx: 100.0, cy: 50.0, w: 20.0, h: 10.0, clamped: false},
+             %{cx: 7.0, cy: 476.0, w: 14.0, h: 7.0, clamped: true}
+           ] =
+             model_output
+             |> YoloFastNMS.run(
+               transpose: false,
+               image_size: {640, 480},
+               clamp: true,
+               format: :map,
+               clamped: true
+             )
+             |> Enum.sort_by(& &1.prob, :desc)
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.run(model_output, clamp: true) end
+  end
+
+  test "detections flagged above the display threshold" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.9, 0, 2),
+        detection_row([100, 0, 10, 20], 0.5, 0, 2),
+        detection_row([200, 0, 10, 20], 0.2, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    options = [transpose: false, prob_threshold: 0.1, format: :map, display: true]
+
+    assert [true, false, false] ==
+             model_output
+             |> YoloFastNMS.run([display_threshold: 0.6] ++ options)
+             |> Enum.sort_by(& &1.prob, :desc)
+             |> Enum.map(& &1.display)
+
+    # defaults to prob_threshold
+    assert Enum.all?(YoloFastNMS.run(model_output, options), & &1.display)
+  end
+
+  test "transposed tensors read column-wise give the same detections as the row-major ones" do
+    # {84, 2000} like a YOLOv8 output, boxes spread over a 640x640 image
+    {values, _key} = Nx.Random.uniform(Nx.Random.key(42), shape: {84, 2000}, type: {:f, 32})
+    model_output = Nx.concatenate([Nx.multiply(values[0..3], 640), values[4..83]])
+    row_major = Nx.transpose(model_output)
+
+    for options <- [[], [format: :map, row_index: true, class_probs: true, row_offset: 100]] do
+      assert model_output |> YoloFastNMS.run(options) |> Enum.sort() ==
+               row_major |> YoloFastNMS.run([transpose: false] ++ options) |> Enum.sort()
+    end
+  end
+
+  test "binary format packs the detections, typed even when empty" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        detection_row([400, 400, 20, 20], 0.7, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert %{data: data, count: 2, columns: 6} =
+             YoloFastNMS.run(model_output, transpose: false, format: :binary)
+
+    assert model_output |> YoloFastNMS.run(transpose: false) |> Nx.tensor(type: {:f, 32}) ==
+             data |> Nx.from_binary({:f, 32}) |> Nx.reshape({2, 6})
+
+    assert %{data: "", count: 0, columns: 6} =
+             YoloFastNMS.run(model_output, transpose: false, format: :binary, prob_threshold: 0.95)
+
+    assert {0, 6} == "" |> Nx.from_binary({:f, 32}) |> Nx.reshape({0, 6}) |> Nx.shape()
+  end
+
+  test "counts of the candidates and of the kept detections" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        # IoU 0.6, suppressed
+        detection_row([110, 100, 40, 40], 0.8, 0, 2),
+        detection_row([400, 400, 20, 20], 0.7, 1, 2),
+        # below prob_threshold
+        detection_row([600, 400, 20, 20], 0.1, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    for iou_threshold <- [0.5, 0.7] do
+      options = [transpose: false, iou_threshold: iou_threshold]
+      {raw_count, filtered_count, kept_count} = YoloFastNMS.counts(model_output, options)
+
+      assert {4, 3} == {raw_count, filtered_count}
+      assert kept_count == length(YoloFastNMS.run(model_output, options))
+    end
+
+    assert {4, 3, 2} == YoloFastNMS.counts(model_output, transpose: false)
+  end
+
+  test "binary format in a forced byte order" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        detection_row([400, 400, 20, 20], 0.7, 1, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    detections = model_output |> YoloFastNMS.run(transpose: false) |> List.flatten()
+
+    %{data: big} =
+      YoloFastNMS.run(model_output, transpose: false, format: :binary, endianness: :big)
+
+    %{data: little} =
+      YoloFastNMS.run(model_output, transpose: false, format: :binary, endianness: :little)
+
+    assert detections == for(<<value::float-32-big <- big>>, do: value)
+    assert detections == for(<<value::float-32-little <- little>>, do: value)
+  end
+
+  test "info returns the version and the features of the NIF build" do
+    assert %{version: version, debug: debug, features: features} = YoloFastNMS.info()
+    assert {:ok, _version} = Version.parse(version)
+    assert is_boolean(debug)
+    assert [:alloc_count, :parallel, :threads] == features |> Map.keys() |> Enum.sort()
+    assert Enum.all?(Map.values(features), &is_boolean/1)
+  end
+
+  test "validate checks the binary size and the type of a shape" do
+    binary = Nx.to_binary(Nx.iota({84, 8400}, type: {:f, 32}))
+
+    assert :ok == YoloFastNMS.validate(byte_size(binary), 84, 8400, {:f, 32})
+    assert :ok == YoloFastNMS.validate(0, 0, 84)
+
+    assert {:error, message} = YoloFastNMS.validate(byte_size(binary), 84, 8401)
+    assert message =~ "binary size (2822400)"
+    assert message =~ "{84, 8401} f32 tensor (2822736)"
+
+    assert {:error, "unsupported tensor type {:f, 16}, only {:f, 32} is supported"} ==
+             YoloFastNMS.validate(div(byte_size(binary), 2), 84, 8400, {:f, 16})
+  end
+
+  test "labels from the class names table" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.9, 0, 3),
+        detection_row([100, 0, 10, 20], 0.8, 1, 3),
+        detection_row([200, 0, 10, 20], 0.7, 2, 3)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    handle =
+      YoloFastNMS.handle_new({3, 7},
+        transpose: false,
+        format: :map,
+        class_names: ["person", "car"]
+      )
+
+    # class 2 is out of the table
+    assert [{0, "person"}, {1, "car"}, {2, nil}] ==
+             handle
+             |> YoloFastNMS.handle_run(model_output)
+             |> Enum.map(&{&1.class_idx, &1.label})
+             |> Enum.sort()
+  end
+
+  test "detections tagged with their row index" do
+    model_output =
+      [
+        detection_row([0, 0, 10, 20], 0.1, 0, 6),
+        detection_row([0, 0, 10, 20], 0.7, 0, 6),
+        detection_row([1, 0, 10, 20], 0.6, 0, 6),
+        detection_row([50, 50, 10, 20], 0.5, 2, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [1, 3] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, format: :map, row_index: true)
+             |> Enum.map(& &1.row_index)
+             |> Enum.sort()
+
+    # the index is relative to the whole tensor, not to the window
+    assert [3] ==
+             model_output
+             |> YoloFastNMS.run(
+               transpose: false,
+               format: :map,
+               row_index: true,
+               row_offset: 3
+             )
+             |> Enum.map(& &1.row_index)
+  end
+
+  test "merging the results of two configurations" do
+    a = [[10, 10, 20, 20, 0.9, 0]]
+    # the first box overlaps the box of `a` with another class
+    b = [[11, 10, 20, 20, 0.7, 1], [100, 100, 10, 10, 0.6, 1]]
+
+    assert MapSet.new([[10, 10, 20, 20, 0.9, 0], [100, 100, 10, 10, 0.6, 1]]) ==
+             YoloFastNMS.merge(a, b)
+             |> round_results()
+             |> MapSet.new()
+
+    assert 3 == length(YoloFastNMS.merge(a, b, agnostic: false))
+  end
+
+  test "merge counts the support of the merged detections" do
+    a = [[10, 10, 20, 20, 0.9, 0], [200, 200, 20, 20, 0.8, 0]]
+    # overlaps the first box of `a`, its support
+    b = [[11, 10, 20, 20, 0.7, 0]]
+
+    assert [[10, 10, 20, 20, 0.9, 0]] ==
+             a |> YoloFastNMS.merge(b, min_support: 1) |> round_results()
+  end
+
+  test "merge rejects the invalid class ids" do
+    for class <- [-1, 70_000] do
+      assert_raise ArgumentError, fn ->
+        YoloFastNMS.merge([[10, 10, 20, 20, 0.9, class]], [])
+      end
+    end
+  end
+
+  test "iou of known overlaps" do
+    assert 1.0 == YoloFastNMS.iou([0, 0, 10, 10], [0, 0, 10, 10])
+    assert 0.0 == YoloFastNMS.iou([0, 0, 10, 10], [20, 20, 10, 10])
+    # half width shift: intersection 50, union 150
+    assert_in_delta 1 / 3, YoloFastNMS.iou([0, 0, 10, 10], [5, 0, 10, 10]), 1.0e-6
+    # nested box: intersection 36, union 100
+    assert_in_delta 0.36, YoloFastNMS.iou([0, 0, 10, 10], [0, 0, 6, 6]), 1.0e-6
+  end
+
+  test "pixel inclusive iou of small boxes" do
+    # continuous: intersection 2, union 6. Pixels: intersection 6, union 12
+    assert_in_delta 1 / 3, YoloFastNMS.iou([2, 2, 2, 2], [3, 2, 2, 2]), 1.0e-6
+    assert_in_delta 0.5, YoloFastNMS.iou([2, 2, 2, 2], [3, 2, 2, 2], pixel_inclusive: true), 1.0e-6
+
+    model_output =
+      [
+        detection_row([2, 2, 2, 2], 0.9, 0, 2),
+        detection_row([3, 2, 2, 2], 0.8, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert 2 == length(YoloFastNMS.run(model_output, transpose: false, iou_threshold: 0.4))
+
+    assert [[2, 2, 2, 2, 0.9, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, iou_threshold: 0.4, pixel_inclusive: true)
+             |> round_results()
+  end
+
+  test "iomin and iomax of a nested pair" do
+    outer = [0, 0, 20, 20]
+    inner = [0, 0, 10, 10]
+
+    # intersection 100, areas 400 and 100
+    assert_in_delta 0.25, YoloFastNMS.iou(outer, inner), 1.0e-6
+    assert_in_delta 1.0, YoloFastNMS.iou(outer, inner, overlap_metric: :iomin), 1.0e-6
+    assert_in_delta 0.25, YoloFastNMS.iou(outer, inner, overlap_metric: :iomax), 1.0e-6
+
+    model_output =
+      [detection_row(outer, 0.9, 0, 2), detection_row(inner, 0.8, 0, 2)]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert 2 == length(YoloFastNMS.run(model_output, transpose: false))
+
+    assert [[0, 0, 20, 20, 0.9, 0]] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, overlap_metric: :iomin)
+             |> round_results()
+  end
+
+  test "f64 iou of large boxes" do
+    a = [0, 0, 10_000, 10_000]
+    b = [1, 1, 10_000, 10_000]
+    # intersection 9999², union 2 * 10000² - 9999²
+    exact = 99_980_001 / 100_019_999
+
+    # the f32 areas are rounded, the error is ~5.0e-8
+    refute exact == YoloFastNMS.iou(a, b)
+    assert_in_delta exact, YoloFastNMS.iou(a, b), 1.0e-7
+    assert exact == YoloFastNMS.iou(a, b, precision: :f64)
+  end
+
+  test "rotated iou of known overlaps" do
+    assert_in_delta 1.0, YoloFastNMS.iou([0, 0, 10, 10, 0], [0, 0, 10, 10, :math.pi() / 2]), 1.0e-5
+    # intersection of a square and the same square rotated by 45° is a regular octagon
+    assert_in_delta :math.sqrt(2) / 2,
+                    YoloFastNMS.iou([0, 0, 10, 10, 0], [0, 0, 10, 10, :math.pi() / 4]),
+                    1.0e-5
+
+    # fully contained
+    assert_in_delta 0.16, YoloFastNMS.iou([0, 0, 10, 10, 0], [0, 0, 4, 4, :math.pi() / 4]), 1.0e-5
+    # a 20x10 box and the same box rotated by 90°, intersection 100, union 300
+    assert_in_delta 1 / 3, YoloFastNMS.iou([0, 0, 20, 10, 0], [0, 0, 20, 10, :math.pi() / 2]), 1.0e-5
+    assert 0.0 == YoloFastNMS.iou([0, 0, 10, 10, 0], [30, 0, 10, 10, :math.pi() / 4])
+  end
+
+  test "rotated iou of the large boxes" do
+    # the areas are above the i32 range
+    assert_in_delta 1.0, YoloFastNMS.iou([0, 0, 70000, 70000, 0], [0, 0, 70000, 70000, 0]), 1.0e-5
+    assert_in_delta 0.5, YoloFastNMS.iou([0, 0, 70000, 70000, 0], [0, 0, 70000, 35000, 0]), 1.0e-5
+  end
+
+  test "oriented boxes suppressed with the rotated iou" do
+    model_output =
+      [
+        detection_row([0, 0, 40, 4], 0.8, 0, 2) ++ [0.0],
+        # crossing the first box, low rotated IoU
+        detection_row([0, 0, 40, 4], 0.7, 0, 2) ++ [:math.pi() / 2],
+        # same box slightly rotated, suppressed
+        detection_row([0, 0, 40, 4], 0.6, 0, 2) ++ [0.05]
+      ]
+      |> Nx.tensor(type: {:f, 32})
+
+    assert [{0.8, 0.0}, {0.7, 1.57}] ==
+             model_output
+             |> YoloFastNMS.run(transpose: false, obb: true)
+             |> Enum.map(fn [_, _, _, _, prob, _, angle] ->
+               {Float.round(prob, 2), Float.round(angle, 2)}
+             end)
+             |> Enum.sort(:desc)
+  end
+
+  test "handle runs match run/2 over repeated frames" do
+    frames =
+      for offset <- [0, 5, 300] do
+        [
+          detection_row([offset, 0, 10, 20], 0.9, 0, 6),
+          detection_row([offset + 1, 0, 10, 20], 0.8, 0, 6),
+          detection_row([offset + 100, 50, 10, 20], 0.6, 2, 6),
+          detection_row([offset, 50, 10, 20], 0.1, 1, 6)
+        ]
+        |> Nx.tensor(type: {:f, 32})
+        |> Nx.transpose(axes: [1, 0])
+      end
+
+    handle = YoloFastNMS.handle_new({10, 4}, prob_threshold: 0.4)
+
+    for frame <- frames ++ frames do
+      assert MapSet.new(YoloFastNMS.run(frame, prob_threshold: 0.4)) ==
+               MapSet.new(YoloFastNMS.handle_run(handle, frame))
+    end
+  end
+
+  test "handle with temporal_iou carries the track ids across the frames" do
+    frame = fn rows ->
+      rows
+      |> Enum.map(fn {bbox, prob, class_idx} -> detection_row(bbox, prob, class_idx, 6) end)
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+    end
+
+    handle =
+      YoloFastNMS.handle_new({10, 2}, prob_threshold: 0.4, format: :map, temporal_iou: 0.5)
+
+    first =
+      handle
+      |> YoloFastNMS.handle_run(frame.([{[20, 20, 10, 20], 0.8, 0}, {[100, 50, 10, 20], 0.6, 2}]))
+      |> Enum.sort_by(& &1.cx)
+
+    assert [0, 1] == first |> Enum.map(& &1.track_id) |> Enum.sort()
+    [%{track_id: person_id}, _car] = first
+
+    # the person moved slightly and keeps its id, the new class 2 box doesn't overlap the old one
+    second =
+      handle
+      |> YoloFastNMS.handle_run(frame.([{[22, 21, 10, 20], 0.7, 0}, {[300, 50, 10, 20], 0.6, 2}]))
+      |> Enum.sort_by(& &1.cx)
+
+    assert [%{cx: 22.0, track_id: ^person_id}, %{cx: 300.0, track_id: 2}] = second
+  end
+
+  test "handle_run overrides the handle options for a single run" do
+    model_output =
+      [
+        detection_row([100, 100, 40, 40], 0.9, 0, 2),
+        # IoU 0.6 with the first box
+        detection_row([110, 100, 40, 40], 0.8, 0, 2)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    handle = YoloFastNMS.handle_new({6, 2}, iou_threshold: 0.5)
+
+    assert 1 == length(YoloFastNMS.handle_run(handle, model_output))
+    assert 2 == length(YoloFastNMS.handle_run(handle, model_output, iou_threshold: 0.7))
+    # the next run uses the handle threshold again
+    assert 1 == length(YoloFastNMS.handle_run(handle, model_output))
+    # IoMin 0.75
+    assert 1 ==
+             length(YoloFastNMS.handle_run(handle, model_output, iou_threshold: 0.7, overlap_metric: :iomin))
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.handle_run(handle, model_output, format: :map) end
+
+    # the overrides are validated with the handle options
+    for options <- [[sort_order: :asc], [geometry: :union]] do
+      handle = YoloFastNMS.handle_new({6, 2}, options)
+      assert 1 == length(YoloFastNMS.handle_run(handle, model_output, iou_threshold: 0.5))
+
+      assert_raise ArgumentError, ~r/requires method: :hard/, fn ->
+        YoloFastNMS.handle_run(handle, model_output, method: :soft)
+      end
+    end
+  end
+
+  test "offer decides each detection offered by prob descending" do
+    handle = YoloFastNMS.handle_new({84, 8400}, iou_threshold: 0.5)
+
+    # IoU 0.6 between the first two, 0.23 between the first and the third
+    assert :kept == YoloFastNMS.offer(handle, [100, 100, 40, 40, 0.9, 0])
+    assert :suppressed == YoloFastNMS.offer(handle, [110, 100, 40, 40, 0.8, 0])
+    assert :kept == YoloFastNMS.offer(handle, [125, 100, 40, 40, 0.7, 0])
+    # another class
+    assert :kept == YoloFastNMS.offer(handle, [110, 100, 40, 40, 0.6, 1])
+
+    assert :ok == YoloFastNMS.offer_reset(handle)
+    assert :kept == YoloFastNMS.offer(handle, [110, 100, 40, 40, 0.8, 0])
+
+    assert_raise ArgumentError, fn -> YoloFastNMS.offer(handle, [110, 100, 40, 40, 0.8]) end
+    # invalid class ids, like the parsing
+    for class_idx <- [-1, 70_000] do
+      assert_raise ArgumentError, fn ->
+        YoloFastNMS.offer(handle, [110, 100, 40, 40, 0.8, class_idx])
+      end
+    end
+
+    # a zero threshold keeps only the most probable detection of each class, like run/2
+    handle = YoloFastNMS.handle_new({84, 8400}, iou_threshold: 0.0)
+    assert :kept == YoloFastNMS.offer(handle, [100, 100, 40, 40, 0.9, 0])
+    assert :suppressed == YoloFastNMS.offer(handle, [500, 500, 40, 40, 0.8, 0])
+    assert :kept == YoloFastNMS.offer(handle, [500, 500, 40, 40, 0.7, 1])
+  end
+
+  test "streaming detections in chunks and running NMS on finalize" do
+    stream = YoloFastNMS.stream_new(10, prob_threshold: 0.4, max_candidates: 4)
+
+    chunk_1 =
+      [
+        detection_row([0, 0, 10, 20], 0.7, 0, 6),
+        detection_row([1, 1, 10, 20], 0.6, 0, 6),
+        detection_row([50, 50, 10, 20], 0.1, 1, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    chunk_2 =
+      [
+        detection_row([100, 100, 10, 20], 0.8, 3, 6),
+        detection_row([200, 200, 10, 20], 0.5, 3, 6)
+      ]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    assert {:ok, 2} = YoloFastNMS.stream_push(stream, chunk_1)
+    assert {:ok, 4} = YoloFastNMS.stream_push(stream, chunk_2)
+    assert {:error, :max_candidates_exceeded} = YoloFastNMS.stream_push(stream, chunk_2)
+    assert {:error, :invalid_chunk_size} = YoloFastNMS.stream_push(stream, <<0, 0, 0, 0>>)
+
+    assert MapSet.new([
+             [0, 0, 10, 20, 0.7, 0],
+             [100, 100, 10, 20, 0.8, 3],
+             [200, 200, 10, 20, 0.5, 3]
+           ]) ==
+             stream
+             |> YoloFastNMS.stream_finalize(iou_threshold: 0.5)
+             |> round_results()
+             |> MapSet.new()
+
+    assert :ok = YoloFastNMS.stream_reset(stream)
+    assert [] == YoloFastNMS.stream_finalize(stream)
+  end
+
+  test "stream_push checks the columns like run" do
+    chunk =
+      [detection_row([0, 0, 10, 20], 0.7, 0, 6)]
+      |> Nx.tensor(type: {:f, 32})
+      |> Nx.transpose(axes: [1, 0])
+
+    stream = YoloFastNMS.stream_new(10, valid_columns: 12)
+    assert_raise ErlangError, fn -> YoloFastNMS.stream_push(stream, chunk) end
+
+    stream = YoloFastNMS.stream_new(10, score_column: 10, class_column: 5)
+    assert_raise ErlangError, fn -> YoloFastNMS.stream_push(stream, chunk) end
+
+    # only the box and the objectness, without any class probability
+    stream = YoloFastNMS.stream_new(10, valid_columns: 5, objectness: true)
+    assert {:ok, 0} = YoloFastNMS.stream_push(stream, chunk)
+  end
+
   defp round_results(results) do
     Enum.map(results, fn [cx, cy, w, h, prob, class_idx] ->
       [